@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // The one trial request after the cooldown is out; nothing else goes until it reports back
+    HalfOpen,
+}
+
+
+// Closed: requests flow. Open: requests are refused until the cooldown ends. Half-open: after the cooldown
+// one trial request is let through; success closes the breaker, failure opens it again
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: State,
+}
+
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: State::Closed { failures: 0 },
+        }
+    }
+
+    // Whether a request may go out now. Once the cooldown is over the first caller gets the half-open trial
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            State::Closed { .. } => true,
+            State::Open { until } if Instant::now() >= until => {
+                self.state = State::HalfOpen;
+                true
+            }
+            State::Open { .. } | State::HalfOpen => false,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.state = State::Closed { failures: 0 };
+    }
+
+    // `retry_after` is the server's own hint; when the breaker opens it stays open at least that long
+    pub fn record_failure(&mut self, retry_after: Option<Duration>) {
+        let failures = match self.state {
+            State::Closed { failures } => failures + 1,
+            State::Open { .. } | State::HalfOpen => self.threshold,
+        };
+
+        self.state = if failures >= self.threshold {
+            let cooldown = retry_after.map_or(self.cooldown, |hint| hint.max(self.cooldown));
+            State::Open { until: Instant::now() + cooldown }
+        } else {
+            State::Closed { failures }
+        };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::CircuitBreaker;
+    use std::time::Duration;
+
+    #[test]
+    fn opens_after_threshold_and_half_opens_after_the_cooldown() {
+        let mut breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        assert!(breaker.allow());
+        breaker.record_failure(None);
+        assert!(breaker.allow(), "one failure is below the threshold");
+        breaker.record_failure(None);
+        assert!(!breaker.allow(), "open right after the threshold");
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow(), "the trial request after the cooldown");
+        assert!(!breaker.allow(), "only one trial while half-open");
+
+        // A failed trial opens it again straight away, without counting up to the threshold
+        breaker.record_failure(None);
+        assert!(!breaker.allow());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert!(breaker.allow() && breaker.allow(), "closed again after a successful trial");
+        breaker.record_failure(None);
+        assert!(breaker.allow(), "the failure count restarts after closing");
+    }
+
+    #[test]
+    fn retry_after_extends_the_cooldown() {
+        let mut breaker = CircuitBreaker::new(1, Duration::from_millis(10));
+        breaker.record_failure(Some(Duration::from_millis(200)));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!breaker.allow(), "still open for the server's Retry-After");
+    }
+}
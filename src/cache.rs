@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+
+
+// Bounded map that evicts the least recently inserted or read entry once `capacity` is exceeded
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (u64, V)>,
+    order: BTreeMap<u64, K>,
+}
+
+
+impl<K, V> LruCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let (tick, value) = self.entries.get_mut(key)?;
+        let old_tick = std::mem::replace(tick, self.tick);
+        if let Some(key) = self.order.remove(&old_tick) {
+            self.order.insert(self.tick, key);
+        }
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tick += 1;
+        if let Some((old_tick, _)) = self.entries.insert(key.clone(), (self.tick, value)) {
+            self.order.remove(&old_tick);
+        }
+        self.order.insert(self.tick, key);
+
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    // Returns the number of entries removed
+    pub fn clear(&mut self) -> usize {
+        let removed = self.entries.len();
+        self.entries.clear();
+        self.order.clear();
+        removed
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::LruCache;
+
+    #[test]
+    fn evicts_the_least_recently_used_entry_past_capacity() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+
+        // Reading "a" makes "b" the oldest
+        assert_eq!(cache.get(&"a"), Some(&1));
+        cache.insert("c", 3);
+        assert!(cache.contains(&"a") && cache.contains(&"c"));
+        assert!(!cache.contains(&"b"));
+
+        // Re-inserting refreshes without growing
+        cache.insert("a", 4);
+        cache.insert("d", 5);
+        assert_eq!(cache.get(&"a"), Some(&4));
+        assert!(!cache.contains(&"c"));
+        assert_eq!(cache.clear(), 2);
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing() {
+        let mut cache = LruCache::new(0);
+        cache.insert("a", 1);
+        assert!(!cache.contains(&"a"));
+    }
+}
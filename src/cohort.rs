@@ -0,0 +1,156 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use hmac::{Hmac, Mac};
+use rand::distributions::WeightedIndex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+use crate::random_module::SecureRandom;
+use crate::{failure, success};
+use crate::utils::Config;
+
+
+
+// BTreeMap keeps the cohort order fixed, which the per-user determinism depends on
+#[derive(Deserialize)]
+struct CohortParams {
+    user_id: String,
+    cohorts: BTreeMap<String, f64>,
+    salt: Option<String>,
+}
+
+
+
+// Stable across restarts and versions: the first 8 bytes of SHA-256 over user id and salt. With a server-side
+// pepper it is HMAC-SHA256 keyed by the pepper instead, so assignments can't be recomputed without it
+fn cohort_seed(user_id: &str, salt: &str, pepper: Option<&str>) -> u64 {
+    let message = [user_id.as_bytes(), &[0u8], salt.as_bytes()].concat();
+    let digest: Vec<u8> = match pepper {
+        Some(pepper) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(pepper.as_bytes()).expect("HMAC accepts keys of any length");
+            mac.update(&message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        None => Sha256::digest(&message).to_vec(),
+    };
+
+    let mut seed = [0u8; 8];
+    seed.copy_from_slice(&digest[..8]);
+    u64::from_le_bytes(seed)
+}
+
+
+fn assign_cohort(params: &CohortParams, pepper: Option<&str>) -> Result<String, String> {
+    if params.cohorts.is_empty() {
+        return Err("At least one cohort is required.".to_string());
+    }
+
+    if let Some((name, weight)) = params.cohorts.iter().find(|(_, w)| !w.is_finite() || **w < 0.0) {
+        return Err(format!("Cohort {:?}: invalid weight {} (weights must be finite and >= 0)", name, weight));
+    }
+
+    if !params.cohorts.values().sum::<f64>().is_finite() {
+        return Err("Weights are too large: their sum is not a finite number.".to_string());
+    }
+
+    let dist = WeightedIndex::new(params.cohorts.values()).map_err(|_| "At least one cohort needs a positive weight.".to_string())?;
+    let seed = cohort_seed(&params.user_id, params.salt.as_deref().unwrap_or_default(), pepper);
+    let mut rng = SecureRandom::from_seed_stream(seed, 0);
+
+    let index = rng.sample(&dist);
+    Ok(params.cohorts.keys().nth(index).cloned().unwrap_or_default())
+}
+
+
+
+#[post("/cohort")]
+pub async fn cohort_handler(req: HttpRequest, params: web::Json<CohortParams>, config: web::Data<Config>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "cohort_handler", "Request from: {}", peer);
+
+    match assign_cohort(&params, config.pepper.as_deref().filter(|pepper| !pepper.is_empty())) {
+        Ok(cohort) => success(cohort),
+        Err(msg) => {
+            warn!(target: "cohort_handler", "{}", msg);
+            HttpResponse::BadRequest().json(failure(msg))
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{assign_cohort, CohortParams};
+
+    fn params(user_id: &str, salt: Option<&str>) -> CohortParams {
+        CohortParams {
+            user_id: user_id.to_string(),
+            cohorts: [("control".to_string(), 1.0), ("treatment".to_string(), 3.0)].into_iter().collect(),
+            salt: salt.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn a_user_always_lands_in_the_same_cohort() {
+        for user in ["alice", "bob", "user-42"] {
+            let first = assign_cohort(&params(user, Some("exp-1")), None).unwrap();
+            for _ in 0..10 {
+                assert_eq!(assign_cohort(&params(user, Some("exp-1")), None).unwrap(), first);
+            }
+        }
+    }
+
+    #[test]
+    fn cohort_sizes_track_the_weights_across_users() {
+        let users = 10_000;
+        let treatment = (0..users)
+            .filter(|i| assign_cohort(&params(&format!("user-{}", i), None), None).unwrap() == "treatment")
+            .count();
+
+        let share = treatment as f64 / users as f64;
+        assert!((share - 0.75).abs() < 0.02, "treatment share {}", share);
+    }
+
+    #[test]
+    fn the_salt_reshuffles_assignments() {
+        let moved = (0..1000)
+            .filter(|i| {
+                let user = format!("user-{}", i);
+                assign_cohort(&params(&user, Some("exp-1")), None).unwrap() != assign_cohort(&params(&user, Some("exp-2")), None).unwrap()
+            })
+            .count();
+
+        // Independent draws disagree 2 * 0.25 * 0.75 = 37.5% of the time
+        assert!((300..450).contains(&moved), "{} of 1000 users moved", moved);
+    }
+
+    #[test]
+    fn the_pepper_reshuffles_assignments() {
+        let moved = |a: Option<&str>, b: Option<&str>| {
+            (0..1000)
+                .filter(|i| {
+                    let params = params(&format!("user-{}", i), Some("exp-1"));
+                    assign_cohort(&params, a).unwrap() != assign_cohort(&params, b).unwrap()
+                })
+                .count()
+        };
+
+        assert_eq!(moved(Some("pepper-1"), Some("pepper-1")), 0);
+        for (a, b) in [(Some("pepper-1"), Some("pepper-2")), (None, Some("pepper-1"))] {
+            let moved = moved(a, b);
+            assert!((300..450).contains(&moved), "{} of 1000 users moved from {:?} to {:?}", moved, a, b);
+        }
+    }
+
+    #[test]
+    fn invalid_weights_are_rejected() {
+        let mut invalid = params("alice", None);
+        invalid.cohorts.insert("broken".to_string(), -1.0);
+        assert_eq!(assign_cohort(&invalid, None).unwrap_err(), "Cohort \"broken\": invalid weight -1 (weights must be finite and >= 0)");
+
+        invalid.cohorts.clear();
+        assert_eq!(assign_cohort(&invalid, None).unwrap_err(), "At least one cohort is required.");
+    }
+}
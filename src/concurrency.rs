@@ -0,0 +1,159 @@
+use actix_web::{dev::{ServiceRequest, ServiceResponse, Transform, Service}, HttpMessage, HttpResponse, Error, body::BoxBody};
+use futures::future::{ok, Ready, LocalBoxFuture};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::rc::Rc;
+use tracing::warn;
+
+use crate::{failure, ClientIp};
+
+
+
+// Probe endpoints are never counted or rejected, so orchestrators can always reach them (also under route_prefix)
+const PROBE_PATHS: &[&str] = &["/healthz", "/ready"];
+
+
+
+#[derive(Default)]
+pub struct InFlight {
+    count: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+
+impl InFlight {
+    pub fn current(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    fn per_ip(&self) -> std::sync::MutexGuard<'_, HashMap<IpAddr, usize>> {
+        self.per_ip.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+// Releases the global slot and, when one was taken, the client's per-IP slot
+struct InFlightGuard(Arc<InFlight>, Option<IpAddr>);
+
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+
+        if let Some(ip) = self.1 {
+            let mut per_ip = self.0.per_ip();
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+
+
+pub struct InFlightLimit {
+    state: Arc<InFlight>,
+    max: Option<usize>,
+    max_per_ip: Option<usize>,
+}
+
+
+impl InFlightLimit {
+    pub fn new(state: Arc<InFlight>, max: Option<usize>, max_per_ip: Option<usize>) -> Self {
+        Self { state, max, max_per_ip }
+    }
+}
+
+
+impl<S> Transform<S, ServiceRequest> for InFlightLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = InFlightLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(InFlightLimitMiddleware {
+            service: Rc::new(service),
+            state: Arc::clone(&self.state),
+            max: self.max,
+            max_per_ip: self.max_per_ip,
+        })
+    }
+}
+
+
+pub struct InFlightLimitMiddleware<S> {
+    service: Rc<S>,
+    state: Arc<InFlight>,
+    max: Option<usize>,
+    max_per_ip: Option<usize>,
+}
+
+
+impl<S> Service<ServiceRequest> for InFlightLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = Rc::clone(&self.service);
+
+        if PROBE_PATHS.iter().any(|probe| req.path().ends_with(probe)) {
+            return Box::pin(async move { svc.call(req).await });
+        }
+
+        let previous = self.state.count.fetch_add(1, Ordering::SeqCst);
+        let mut guard = InFlightGuard(Arc::clone(&self.state), None);
+
+        if self.max.is_some_and(|max| previous >= max) {
+            drop(guard);
+            warn!(target: "concurrency", "Rejecting {}: {} requests already in flight", req.path(), previous);
+            let response = HttpResponse::ServiceUnavailable()
+                .json(failure("Server is at its concurrency limit, retry later"));
+            return Box::pin(async move { Ok(req.into_response(response)) });
+        }
+
+        // Keyed on the client address resolved by LocalNetworkOnly, so clients behind a trusted proxy are told apart
+        let client_ip = req.extensions().get::<ClientIp>().map(|client| client.0).or_else(|| req.peer_addr().map(|addr| addr.ip()));
+        if let (Some(max_per_ip), Some(ip)) = (self.max_per_ip, client_ip) {
+            let mut per_ip = self.state.per_ip();
+            let count = per_ip.entry(ip).or_insert(0);
+            if *count >= max_per_ip {
+                let current = *count;
+                drop(per_ip);
+                drop(guard);
+                warn!(target: "concurrency", "Rejecting {} from {}: {} of its requests already in flight", req.path(), ip, current);
+                let response = HttpResponse::TooManyRequests()
+                    .json(failure("Too many concurrent requests from this address, retry later"));
+                return Box::pin(async move { Ok(req.into_response(response)) });
+            }
+            *count += 1;
+            guard.1 = Some(ip);
+        }
+
+        Box::pin(async move {
+            let res = svc.call(req).await;
+            drop(guard);
+            res
+        })
+    }
+}
@@ -0,0 +1,46 @@
+use rand::Rng;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::{Level, Metadata};
+
+
+
+thread_local! {
+    // Set while a request that log_sample_rate left out is being polled on this thread
+    static UNSAMPLED: Cell<bool> = const { Cell::new(false) };
+}
+
+
+
+// Drops the per-request info lines (targets ending in `_handler`) of unsampled requests; warnings and errors
+// always pass, so failed requests stay visible whatever the rate
+pub fn keep_event(meta: &Metadata<'_>) -> bool {
+    !(*meta.level() == Level::INFO && meta.target().ends_with("_handler") && UNSAMPLED.get())
+}
+
+
+// Decided once per request, so a request's lines are kept or dropped together
+pub fn sample_request<F: Future>(request: F, rate: Option<f64>) -> SampledRequest<F> {
+    let logged = rate.is_none_or(|rate| rand::thread_rng().gen_bool(rate));
+    SampledRequest { request: Box::pin(request), logged }
+}
+
+
+pub struct SampledRequest<F> {
+    request: Pin<Box<F>>,
+    logged: bool,
+}
+
+
+impl<F: Future> Future for SampledRequest<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let previous = UNSAMPLED.replace(!self.logged);
+        let result = self.request.as_mut().poll(ctx);
+        UNSAMPLED.set(previous);
+        result
+    }
+}
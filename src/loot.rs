@@ -0,0 +1,173 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use rand::distributions::{Uniform, WeightedIndex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::random_module::SecureRandom;
+use crate::{failure, os_rng, success, MAX_COUNT};
+
+
+
+#[derive(Deserialize)]
+struct LootEntry {
+    item: Value,
+    weight: f64,
+    min_quantity: Option<u64>,
+    max_quantity: Option<u64>,
+}
+
+
+#[derive(Deserialize)]
+struct LootParams {
+    entries: Vec<LootEntry>,
+    rolls: Option<usize>,
+}
+
+
+#[derive(Serialize)]
+struct LootDrop {
+    item: Value,
+    quantity: u64,
+}
+
+
+
+// Validated table: one weighted pick of the entry, then a uniform pick of its quantity
+struct LootTable {
+    items: Vec<Value>,
+    picker: WeightedIndex<f64>,
+    quantities: Vec<Uniform<u64>>,
+}
+
+
+impl LootTable {
+    fn new(entries: Vec<LootEntry>) -> Result<Self, String> {
+        if entries.is_empty() {
+            return Err("Loot table must have at least one entry.".to_string());
+        }
+
+        let mut items = Vec::with_capacity(entries.len());
+        let mut weights = Vec::with_capacity(entries.len());
+        let mut quantities = Vec::with_capacity(entries.len());
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            if !entry.weight.is_finite() || entry.weight < 0.0 {
+                return Err(format!("Entry {}: invalid weight {} (weights must be finite and >= 0)", index, entry.weight));
+            }
+
+            let min = entry.min_quantity.unwrap_or(1);
+            let max = entry.max_quantity.unwrap_or(min);
+            if min > max {
+                return Err(format!("Entry {}: min_quantity {} is greater than max_quantity {}", index, min, max));
+            }
+
+            items.push(entry.item);
+            weights.push(entry.weight);
+            quantities.push(Uniform::new_inclusive(min, max));
+        }
+
+        if !weights.iter().sum::<f64>().is_finite() {
+            return Err("Weights are too large: their sum is not a finite number.".to_string());
+        }
+
+        let picker = WeightedIndex::new(&weights).map_err(|_| "At least one entry needs a positive weight.".to_string())?;
+
+        Ok(Self { items, picker, quantities })
+    }
+
+    fn draw(&self, rng: &mut SecureRandom) -> LootDrop {
+        let index = rng.sample(&self.picker);
+        LootDrop {
+            item: self.items[index].clone(),
+            quantity: rng.sample(&self.quantities[index]),
+        }
+    }
+}
+
+
+
+#[post("/loot")]
+pub async fn loot_handler(req: HttpRequest, params: web::Json<LootParams>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "loot_handler", "Request from: {}", peer);
+
+    let LootParams { entries, rolls } = params.into_inner();
+    let rolls = rolls.unwrap_or(1);
+
+    if rolls == 0 || rolls > MAX_COUNT {
+        let msg = format!("Invalid rolls: {} (must be 1–{})", rolls, MAX_COUNT);
+        warn!(target: "loot_handler", "{}", msg);
+        return HttpResponse::BadRequest().json(failure(msg));
+    }
+
+    let table = match LootTable::new(entries) {
+        Ok(table) => table,
+        Err(msg) => {
+            warn!(target: "loot_handler", "{}", msg);
+            return HttpResponse::BadRequest().json(failure(msg));
+        }
+    };
+
+    let mut rng = match os_rng() {
+        Ok(rng) => rng,
+        Err(err) => return err.to_response(),
+    };
+    let drops: Vec<LootDrop> = (0..rolls).map(|_| table.draw(&mut rng)).collect();
+
+    success(drops)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{LootEntry, LootTable};
+    use crate::random_module::SecureRandom;
+
+    fn table(entries: serde_json::Value) -> Result<LootTable, String> {
+        let entries: Vec<LootEntry> = serde_json::from_value(entries).unwrap();
+        LootTable::new(entries)
+    }
+
+    #[test]
+    fn drops_follow_the_weights_and_quantity_ranges() {
+        let table = table(json!([
+            { "item": "gold", "weight": 3.0, "min_quantity": 10, "max_quantity": 20 },
+            { "item": "gem", "weight": 1.0 },
+            { "item": "nothing", "weight": 0.0 },
+        ]))
+        .unwrap();
+        let mut rng = SecureRandom::from_seed_stream(401, 0);
+        let trials = 10_000;
+
+        let mut gold = 0;
+        for _ in 0..trials {
+            let drop = table.draw(&mut rng);
+            match drop.item.as_str().unwrap() {
+                "gold" => {
+                    gold += 1;
+                    assert!((10..=20).contains(&drop.quantity), "gold quantity {}", drop.quantity);
+                }
+                "gem" => assert_eq!(drop.quantity, 1),
+                other => panic!("{} has weight 0", other),
+            }
+        }
+
+        let share = gold as f64 / trials as f64;
+        assert!((share - 0.75).abs() < 0.02, "gold share {}", share);
+    }
+
+    #[test]
+    fn invalid_tables_are_rejected() {
+        let err = |entries| table(entries).err().unwrap();
+
+        assert_eq!(err(json!([])), "Loot table must have at least one entry.");
+        assert_eq!(err(json!([{ "item": 1, "weight": -1.0 }])), "Entry 0: invalid weight -1 (weights must be finite and >= 0)");
+        assert_eq!(err(json!([{ "item": 1, "weight": 1.0 }, { "item": 2, "weight": 1.0, "min_quantity": 5, "max_quantity": 2 }])), "Entry 1: min_quantity 5 is greater than max_quantity 2");
+        assert_eq!(err(json!([{ "item": 1, "weight": 0.0 }])), "At least one entry needs a positive weight.");
+        assert_eq!(err(json!([{ "item": 1, "weight": 1e308 }, { "item": 2, "weight": 1e308 }])), "Weights are too large: their sum is not a finite number.");
+    }
+}
@@ -5,34 +5,100 @@ Version: 5.7.44 r
 
 */
 
-use actix_web::{dev::{ServiceRequest, ServiceResponse, Transform, Service}, get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Error, body::BoxBody};
+use actix_web::{dev::{ServiceRequest, ServiceResponse, Transform, Service}, get, guard, http::header, post, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, Error, body::BoxBody};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use futures::future::{ok, Ready, LocalBoxFuture};
+use futures::StreamExt;
+use once_cell::sync::OnceCell;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use std::task::{Context, Poll};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::rc::Rc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
 use std::time::Instant;
-use tokio::time::Duration;
+use tokio::time::{sleep, Duration};
 use tracing::{info, warn, error};
 
 mod status;
 mod utils;
 mod random_module;
-
+mod policy;
+mod rpc;
+mod concurrency;
+mod payload;
+mod cache;
+mod loot;
+mod cohort;
+mod trace;
+mod breaker;
+mod metrics;
+mod log_sampling;
+mod signing;
+#[cfg(unix)]
+mod syslog;
+mod ws;
+
+#[cfg(test)]
+mod tests;
+
+use cache::LruCache;
+use concurrency::{InFlight, InFlightLimit};
+use trace::TraceBuffer;
+use metrics::Metrics;
+use payload::{PayloadSizeGuard, DEFAULT_MAX_BODY_BYTES, DEFAULT_MAX_STREAM_BYTES};
+use policy::PasswordPolicy;
+use random_module::{CharClass, SecureRandom};
 use status::get_status;
-use utils::{fetch_port, init_tracing, load_config, get_local_ip};
+use utils::{fetch_port, init_tracing, install_panic_hook, load_config, get_local_ip, Config, LogLevelHandle};
 
 
 
 const MAX_LENGTH: usize = 256;
 const MAX_COUNT: usize = 100;
+const MAX_POLICY_ATTEMPTS: usize = 1000;
+const ENTROPY_HEADER: &str = "X-Entropy-Bits";
+const DEFAULT_MAX_JSON_DEPTH: usize = 8;
+const DEFAULT_RECENT_WINDOW: usize = 1000;
+const MAX_RECENT_RETRIES: usize = 16;
+const DEFAULT_DEDUP_CACHE_SIZE: usize = 1000;
+const DEFAULT_DEDUP_TTL_SECS: u64 = 10;
+const DEFAULT_SEEDED_CACHE_SIZE: usize = 1000;
+const MAX_HISTOGRAM_TRIALS: usize = 100_000;
+const MAX_CHOOSE_MANY_PICKS: usize = 100_000;
+const MAX_COORDS: usize = 10_000;
+const MAX_PERMUTATION_K: usize = 100_000;
+const ENCODINGS: &[&str] = &["utf8", "hex", "base64"];
+const DEFAULT_MIN_CHARSET_SIZE: usize = 2;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const SELF_TEST_BUCKETS: usize = 16;
+const SELF_TEST_SAMPLES: usize = 16_000;
+const DEFAULT_TRACE_LINES: usize = 200;
+const CHAOS_MARKER: &str = "Injected failure (chaos_error_rate)";
 
 
 
 // --- local network protect ---
 
 
-pub struct LocalNetworkOnly;
+// Client address as resolved by LocalNetworkOnly (the forwarded one behind a trusted proxy), for later middleware
+#[derive(Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+
+// Forwarding headers are only believed when the socket peer is one of `trusted_proxies`
+pub struct LocalNetworkOnly {
+    trusted_proxies: Rc<Vec<IpAddr>>,
+}
+
+
+impl LocalNetworkOnly {
+    pub fn new(trusted_proxies: Vec<IpAddr>) -> Self {
+        Self { trusted_proxies: Rc::new(trusted_proxies) }
+    }
+}
 
 
 impl<S> Transform<S, ServiceRequest> for LocalNetworkOnly
@@ -49,6 +115,7 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ok(LocalNetworkOnlyMiddleware {
             service: Rc::new(service),
+            trusted_proxies: Rc::clone(&self.trusted_proxies),
         })
     }
 }
@@ -56,6 +123,7 @@ where
 
 pub struct LocalNetworkOnlyMiddleware<S> {
     service: Rc<S>,
+    trusted_proxies: Rc<Vec<IpAddr>>,
 }
 
 
@@ -75,15 +143,22 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = Rc::clone(&self.service);
 
-        let ip_opt = req.connection_info().realip_remote_addr()
-            .and_then(|addr| addr.split(':').next())
-            .and_then(|ip_str| ip_str.parse::<IpAddr>().ok());
+        let peer_ip = req.peer_addr().map(|addr| addr.ip());
+
+        let ip_opt = match peer_ip {
+            Some(ip) if self.trusted_proxies.contains(&ip) => forwarded_client_ip(&req, ip, &self.trusted_proxies),
+            _ => peer_ip,
+        };
 
         let allowed = match ip_opt {
             Some(ip) => is_local_ip(&ip),
             None => false,
         };
 
+        if let Some(ip) = ip_opt {
+            req.extensions_mut().insert(ClientIp(ip));
+        }
+
         if allowed {
             Box::pin(async move { svc.call(req).await })
         } else {
@@ -97,6 +172,50 @@ where
 }
 
 
+// Proxies append the address they received from, so only the right end of the chain is trustworthy: walk it
+// from the right past our own proxies and take the first hop we don't control. Leftmost entries are whatever
+// the client sent. `Forwarded` is used when present, otherwise `X-Forwarded-For`; no header means the proxy
+// itself is the client
+fn forwarded_client_ip(req: &ServiceRequest, peer_ip: IpAddr, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let forwarded: Vec<&str> = req.headers().get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split([',', ';']))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+        .map(|(_, addr)| addr)
+        .collect();
+
+    let hops = if forwarded.is_empty() {
+        req.headers().get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect()
+    } else {
+        forwarded
+    };
+
+    let mut client = peer_ip;
+    for hop in hops.iter().rev() {
+        // An unreadable hop can't be judged, so the request is not let through on it
+        client = parse_forwarded_addr(hop)?;
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+
+// `203.0.113.9`, `203.0.113.9:4711`, `::1`, `[::1]:4711`, optionally quoted as in `Forwarded`
+fn parse_forwarded_addr(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim().trim_matches('"');
+    addr.parse::<SocketAddr>().map(|socket| socket.ip())
+        .or_else(|_| addr.parse::<IpAddr>())
+        .or_else(|_| addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>())
+        .ok()
+}
+
+
 fn is_local_ip(ip: &IpAddr) -> bool {
     match ip {
         IpAddr::V4(ipv4) => ipv4.is_loopback() || ipv4.is_private(),
@@ -109,13 +228,46 @@ fn is_local_ip(ip: &IpAddr) -> bool {
 
 
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 struct GenerateParams {
+    #[serde(default)]
     use_digits: bool,
+    #[serde(default)]
     use_lowercase: bool,
+    #[serde(default)]
     use_uppercase: bool,
+    #[serde(default)]
     use_spec: bool,
     length: usize,
+    policy: Option<PasswordPolicy>,
+    max_classes: Option<usize>,
+    seed: Option<u64>,
+    counter: Option<u64>,
+    export_seed: Option<bool>,
+    syllable_pattern: Option<String>,
+    identifier_safe: Option<bool>,
+    luhn_valid: Option<bool>,
+    class_breakdown: Option<bool>,
+    denylist: Option<String>,
+    min_unique_chars: Option<usize>,
+    crockford_check: Option<bool>,
+    avoid_recent: Option<bool>,
+    charset_preset: Option<String>,
+    dedup_by_body: Option<bool>,
+    encodings: Option<Vec<String>>,
+    position_constraints: Option<Vec<PositionRule>>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    provenance: Option<bool>,
+}
+
+
+// Positions `start..=end` (0-based; `end` defaults to `start`) may only use characters of `classes`
+#[derive(Serialize, Deserialize, Clone)]
+struct PositionRule {
+    start: usize,
+    end: Option<usize>,
+    classes: Vec<CharClass>,
 }
 
 
@@ -126,14 +278,348 @@ struct ApiResponse<T> {
 }
 
 
+// Set once at startup from legacy_error_format
+static LEGACY_ERROR_FORMAT: OnceCell<bool> = OnceCell::new();
+
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Failure<T> {
+    Envelope(ApiResponse<T>),
+    Legacy { error: T },
+}
+
+
+// Body of every failure response: the usual envelope, or `{ "error": msg }` for older clients
+fn failure<T: Serialize>(msg: T) -> Failure<T> {
+    failure_in(LEGACY_ERROR_FORMAT.get().copied().unwrap_or(false), msg)
+}
+
+
+fn failure_in<T: Serialize>(legacy: bool, msg: T) -> Failure<T> {
+    if legacy {
+        Failure::Legacy { error: msg }
+    } else {
+        Failure::Envelope(ApiResponse { success: false, data: msg })
+    }
+}
+
+
+// Success responses are serialized here first, so data that cannot be written as JSON gives a structured 500
+// instead of actix's plain-text error. Non-finite floats never fail: serde_json writes them as null
+fn success<T: Serialize>(data: T) -> HttpResponse {
+    match serialize_body(&ApiResponse { success: true, data }) {
+        Some(bytes) => HttpResponse::Ok().insert_header(header::ContentType::json()).body(bytes),
+        None => unserializable(),
+    }
+}
+
+
+fn serialize_body<T: Serialize>(body: &T) -> Option<Vec<u8>> {
+    serde_json::to_vec(body)
+        .map_err(|e| error!(target: "serialization", "Failed to serialize response body: {}", e))
+        .ok()
+}
+
+
+fn unserializable() -> HttpResponse {
+    HttpResponse::InternalServerError().json(failure("Response could not be serialized"))
+}
+
+
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+enum GenerateOutput {
+    Plain(String),
+    Detailed(Box<StringResult>),
+}
+
+
+#[derive(Serialize, Clone, Default)]
+struct StringResult {
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy: Option<PasswordPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counter: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encodings: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    class_breakdown: Option<ClassBreakdown>,
+}
+
+
+// Characters of the random part per class; non-alphanumerics all count as special
+#[derive(Serialize, Clone, Default)]
+struct ClassBreakdown {
+    digits: usize,
+    lowercase: usize,
+    uppercase: usize,
+    special: usize,
+}
+
+
+impl ClassBreakdown {
+    fn of(value: &str) -> Self {
+        let mut breakdown = Self::default();
+        for c in value.chars() {
+            match CharClass::of(c) {
+                CharClass::Digits => breakdown.digits += 1,
+                CharClass::Lowercase => breakdown.lowercase += 1,
+                CharClass::Uppercase => breakdown.uppercase += 1,
+                CharClass::Special => breakdown.special += 1,
+            }
+        }
+        breakdown
+    }
+}
+
+
+// Audit metadata describing how a value was produced
+#[derive(Serialize, Clone)]
+struct Provenance {
+    algorithm: &'static str,
+    crate_version: &'static str,
+    generated_at: String,
+}
+
+
 #[derive(Deserialize)]
+struct LogLevelParams {
+    level: String,
+}
+
+
+#[derive(Deserialize, Default, Clone)]
 struct ChooseParams<T> {
     items: Vec<T>,
     count: usize,
+    dedup_normalized: Option<bool>,
+    weights_map: Option<HashMap<String, f64>>,
+    weight_by_length: Option<bool>,
+    strata_key: Option<String>,
+    distinct_output: Option<bool>,
+    recency_key: Option<String>,
+    half_life_secs: Option<f64>,
+    shuffle_key: Option<String>,
+    weight_pointer: Option<String>,
+    join: Option<String>,
+    ranked: Option<bool>,
+    inverse_frequency: Option<bool>,
+}
+
+
+// Options for a text/plain choose body, which carries only the items
+#[derive(Deserialize)]
+struct TextChooseQuery {
+    count: Option<usize>,
+    dedup_normalized: Option<bool>,
+    weight_by_length: Option<bool>,
+}
+
+
+#[derive(Deserialize)]
+struct StreamChooseQuery {
+    count: Option<usize>,
+}
+
+
+// Body of POST /generate: the operation's usual parameters plus a "type" naming it
+#[derive(Deserialize)]
+#[serde(try_from = "Value")]
+enum TypedGenerateParams {
+    String(GenerateParams),
+    Choose(ChooseParams<Value>),
+}
+
+
+// By hand rather than an internally tagged enum, so a missing "type" gets the same message as an unknown one
+impl TryFrom<Value> for TypedGenerateParams {
+    type Error = String;
+
+    fn try_from(mut body: Value) -> Result<Self, String> {
+        let kind = body.as_object_mut().and_then(|fields| fields.remove("type"));
+        match kind.as_ref().and_then(Value::as_str) {
+            Some("string") => serde_json::from_value(body).map(Self::String).map_err(|err| err.to_string()),
+            Some("choose") => serde_json::from_value(body).map(Self::Choose).map_err(|err| err.to_string()),
+            _ => Err(format!("\"type\" must be \"string\" or \"choose\", got {}", kind.unwrap_or(Value::Null))),
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+struct CheckPolicyParams {
+    value: String,
+    policy: PasswordPolicy,
+}
+
+
+#[derive(Serialize)]
+struct PolicyReport {
+    passed: bool,
+    rules: Vec<policy::RuleCheck>,
+}
+
+
+// The charset-shaping subset of GenerateParams; max_classes is left out since it picks classes at random per request
+#[derive(Deserialize)]
+struct CharsetInfoQuery {
+    #[serde(default)]
+    use_digits: bool,
+    #[serde(default)]
+    use_lowercase: bool,
+    #[serde(default)]
+    use_uppercase: bool,
+    #[serde(default)]
+    use_spec: bool,
+    charset_preset: Option<String>,
+    denylist: Option<String>,
+    identifier_safe: Option<bool>,
+}
+
+
+#[derive(Serialize)]
+struct CharsetInfo {
+    charset: String,
+    size: usize,
+    excluded: String,
+}
+
+
+#[derive(Deserialize)]
+struct ChooseManyParams {
+    items: Vec<Value>,
+    draws: usize,
+    count_per_draw: usize,
+    weights_map: Option<HashMap<String, f64>>,
+}
+
+
+#[derive(Deserialize)]
+struct CoordsParams {
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+    count: usize,
+    on_sphere: Option<bool>,
+}
+
+
+#[derive(Deserialize)]
+struct PartialPermutationParams {
+    n: u64,
+    k: usize,
+}
+
+
+#[derive(Serialize)]
+struct Coordinate {
+    lat: f64,
+    lon: f64,
+}
+
+
+#[derive(Deserialize)]
+struct HistogramParams {
+    items: Vec<Value>,
+    trials: usize,
+    weights_map: Option<HashMap<String, f64>>,
+}
+
+
+// Values handed out with `avoid_recent`, so later opted-in requests can steer clear of them
+pub type RecentValues = Mutex<LruCache<String, ()>>;
+
+
+// Wakes the task in main that stops the server
+type StopSender = tokio::sync::mpsc::UnboundedSender<()>;
+
+
+// Results of `dedup_by_body` requests, keyed by the request body in canonical JSON form
+type BodyCache = Mutex<LruCache<String, (Instant, Generated<GenerateOutput>)>>;
+type SeededCache = Mutex<LruCache<String, Generated<GenerateOutput>>>;
+
+
+#[derive(Clone)]
+struct Generated<T> {
+    output: T,
+    entropy_bits: f64,
+}
+
+
+enum ServiceError {
+    Invalid(String),
+    TooLarge(String),
+    Internal(&'static str),
+    Injected,
+}
+
+
+impl ServiceError {
+    fn to_response(&self) -> HttpResponse {
+        match self {
+            ServiceError::Invalid(msg) => HttpResponse::BadRequest().json(failure(msg)),
+            ServiceError::TooLarge(msg) => HttpResponse::PayloadTooLarge().json(failure(msg)),
+            ServiceError::Internal(_) => HttpResponse::InternalServerError().json(failure("Internal server error")),
+            ServiceError::Injected => HttpResponse::InternalServerError().json(failure(CHAOS_MARKER)),
+        }
+    }
+}
+
+
+// Logs a failed request under the handler's target: client mistakes as warnings, our own failures as errors.
+// A macro because tracing targets must be literals
+macro_rules! log_failure {
+    ($target:literal, $err:expr) => {
+        match $err {
+            ServiceError::Invalid(msg) | ServiceError::TooLarge(msg) => warn!(target: $target, "{}", msg),
+            ServiceError::Internal(msg) => error!(target: $target, "{}", msg),
+            ServiceError::Injected => warn!(target: $target, "{}", CHAOS_MARKER),
+        }
+    };
+}
+
+
+// Only OS entropy reads fail with rand::Error, and only once entropy_retries is used up
+impl From<rand::Error> for ServiceError {
+    fn from(e: rand::Error) -> Self {
+        error!(target: "random_module", "Seeding failed after entropy_retries: {}", e);
+        ServiceError::Internal("OS entropy unavailable")
+    }
+}
+
+
+
+fn json_error_handler(err: actix_web::error::JsonPayloadError, req: &HttpRequest) -> Error {
+    let response = match &err {
+        actix_web::error::JsonPayloadError::ContentType => {
+            warn!(target: "json_extractor", "Unsupported Content-Type on {}", req.path());
+            HttpResponse::UnsupportedMediaType().json(failure("Content-Type must be application/json"))
+        }
+        _ => {
+            warn!(target: "json_extractor", "Invalid JSON body on {}: {}", req.path(), err);
+            HttpResponse::BadRequest().json(failure(format!("Invalid JSON body: {}", err)))
+        }
+    };
+    actix_web::error::InternalError::from_response(err, response).into()
 }
 
 
 
+#[get("/")]
+async fn ui_handler() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+
 #[get("/status")]
 async fn status_handler(start: web::Data<Instant>, req: HttpRequest) -> impl Responder {
     let client_addr = req
@@ -149,124 +635,1550 @@ async fn status_handler(start: web::Data<Instant>, req: HttpRequest) -> impl Res
 }
 
 
-#[get("/stop")]
-async fn stop_handler() -> impl Responder {
-    info!(target: "control", "Received /stop request. Exiting...");
+#[get("/healthz")]
+async fn healthz_handler() -> impl Responder {
+    success("ok")
+}
+
+
+#[get("/ready")]
+async fn ready_handler(in_flight: web::Data<InFlight>, config: web::Data<Config>) -> impl Responder {
+    let current = in_flight.current();
+
+    match config.max_concurrent_requests {
+        Some(max) if current >= max => {
+            warn!(target: "ready_handler", "Not ready: {} of {} request slots in use", current, max);
+            HttpResponse::ServiceUnavailable().json(failure("at capacity"))
+        }
+        _ => success("ready"),
+    }
+}
 
-    tokio::spawn(async {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        std::process::exit(0);
+
+#[get("/self/limits")]
+async fn limits_handler(config: web::Data<Config>) -> impl Responder {
+    let limits = serde_json::json!({
+        "max_length": MAX_LENGTH,
+        "min_length": config.min_length.unwrap_or(1).max(1),
+        "min_charset_size": config.min_charset_size.unwrap_or(DEFAULT_MIN_CHARSET_SIZE),
+        "max_count": MAX_COUNT,
+        "max_items": config.max_items,
+        "max_histogram_trials": MAX_HISTOGRAM_TRIALS,
+        "max_choose_many_picks": MAX_CHOOSE_MANY_PICKS,
+        "max_coords": MAX_COORDS,
+        "max_permutation_k": MAX_PERMUTATION_K,
+        "max_json_depth": config.max_json_depth.unwrap_or(DEFAULT_MAX_JSON_DEPTH),
+        "max_concurrent_requests": config.max_concurrent_requests,
+        "max_body_bytes": config.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES),
+        "max_stream_bytes": config.max_stream_bytes.unwrap_or(DEFAULT_MAX_STREAM_BYTES),
     });
 
-    HttpResponse::Ok().json(serde_json::json!({ "success": true, "data": null }))
+    success(limits)
 }
 
 
-#[post("/generate_random_string")]
-async fn generate_handler(req: HttpRequest, params: web::Json<GenerateParams>) -> impl Responder {
-    let start = Instant::now();
-    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
-    info!(target: "generate_handler", "Request from: {}", peer);
+// Quick fairness check of the live RNG: uniform draws into equal buckets, judged by chi-square
+#[get("/self/test")]
+async fn self_test_handler() -> HttpResponse {
+    let samples = match os_rng() {
+        Ok(mut rng) => random_module::uniform_indices(&mut rng, SELF_TEST_BUCKETS, SELF_TEST_SAMPLES),
+        Err(err) => return err.to_response(),
+    };
+    let chi_square = random_module::distribution_uniformity(&samples, SELF_TEST_BUCKETS);
+    let critical = random_module::chi_square_critical(SELF_TEST_BUCKETS - 1);
+    let passed = chi_square < critical;
+
+    if !passed {
+        warn!(target: "self_test", "RNG self-test failed: chi-square {:.2} exceeds {:.2}", chi_square, critical);
+    }
+
+    success(serde_json::json!({
+        "buckets": SELF_TEST_BUCKETS,
+        "samples": SELF_TEST_SAMPLES,
+        "chi_square": chi_square,
+        "critical_value": critical,
+        "passed": passed,
+    }))
+}
+
 
-    if params.length == 0 || params.length > MAX_LENGTH {
-        let msg = format!("Invalid length: {} (must be 1–{})", params.length, MAX_LENGTH);
-        warn!(target: "generate_handler", "{}", msg);
-        return HttpResponse::BadRequest().json(ApiResponse { success: false, data: msg });
+// 403 unless allow_admin_endpoints is set; `what` names the rejected request in the log
+fn admin_disabled(config: &Config, what: &str) -> Option<HttpResponse> {
+    if config.allow_admin_endpoints.unwrap_or(false) {
+        return None;
     }
 
-    if !(params.use_digits || params.use_lowercase || params.use_uppercase || params.use_spec) {
-        let msg = "At least one charset must be enabled (digits, lowercase, uppercase, special).";
-        warn!(target: "generate_handler", "{}", msg);
-        return HttpResponse::BadRequest().json(ApiResponse { success: false, data: msg.to_string() });
+    warn!(target: "control", "Rejected {} request: admin endpoints are disabled", what);
+    Some(HttpResponse::Forbidden().json(failure("Admin endpoints are disabled (set allow_admin_endpoints in config)")))
+}
+
+
+#[get("/config")]
+async fn config_handler(config: web::Data<Config>, req: HttpRequest) -> impl Responder {
+    if let Some(forbidden) = admin_disabled(&config, "config") {
+        return forbidden;
     }
 
-    let result = std::panic::catch_unwind(|| {
-        random_module::generate_random_string(
-            params.use_digits,
-            params.use_lowercase,
-            params.use_uppercase,
-            params.use_spec,
-            params.length,
-        )
-    });
+    let client_addr = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "config_handler", "Client {} requested effective config", client_addr);
 
-    match result {
-        Ok(output) => {
-            let duration = start.elapsed().as_millis();
-            info!(target: "generate_handler", "Generation completed in {} ms", duration);
-            HttpResponse::Ok().json(ApiResponse { success: true, data: output })
+    success(config.redacted())
+}
+
+
+#[post("/log_level")]
+async fn log_level_handler(params: web::Json<LogLevelParams>, handle: web::Data<LogLevelHandle>, config: web::Data<Config>) -> impl Responder {
+    if let Some(forbidden) = admin_disabled(&config, "log level") {
+        return forbidden;
+    }
+
+    let filter = match tracing_subscriber::EnvFilter::try_new(&params.level) {
+        Ok(filter) => filter,
+        Err(e) => {
+            let msg = format!("Invalid log level {:?}: {}", params.level, e);
+            warn!(target: "control", "{}", msg);
+            return HttpResponse::BadRequest().json(failure(msg));
+        }
+    };
+
+    match handle.reload(filter) {
+        Ok(()) => {
+            warn!(target: "control", "Log level changed to {}", params.level);
+            success(&params.level)
         }
-        Err(_) => {
-            error!(target: "generate_handler", "Panic occurred during string generation");
-            HttpResponse::InternalServerError().json(ApiResponse { success: false, data: "Internal server error".to_string() })
+        Err(e) => {
+            error!(target: "control", "Failed to reload log filter: {}", e);
+            HttpResponse::InternalServerError().json(failure("Internal server error"))
         }
     }
 }
 
 
-#[post("/generate_random_choose")]
-async fn choose_handler(req: HttpRequest, params: web::Json<ChooseParams<String>>) -> impl Responder {
+#[get("/stop")]
+async fn stop_handler(config: web::Data<Config>, stop: web::Data<StopSender>) -> impl Responder {
+    if !config.allow_remote_stop.unwrap_or(false) {
+        warn!(target: "control", "Rejected /stop request: remote stop is disabled");
+        return HttpResponse::Forbidden().json(failure("Remote stop is disabled (set allow_remote_stop in config)"));
+    }
+
+    info!(target: "control", "Received /stop request. Shutting down gracefully...");
+
+    // main owns the server handle; the receiver is gone only if shutdown has already begun
+    let _ = stop.send(());
+
+    HttpResponse::Ok().json(serde_json::json!({ "success": true, "data": null }))
+}
+
+
+#[post("/admin/cache/clear")]
+async fn cache_clear_handler(config: web::Data<Config>, recent: web::Data<RecentValues>, body_cache: web::Data<BodyCache>, seeded_cache: web::Data<SeededCache>) -> impl Responder {
+    if let Some(forbidden) = admin_disabled(&config, "cache clear") {
+        return forbidden;
+    }
+
+    let dedup = lock(&body_cache).clear();
+    let seeded = lock(&seeded_cache).clear();
+    let recent = lock(&recent).clear();
+    info!(target: "control", "Cleared caches: {} dedup_by_body entries, {} seeded entries, {} avoid_recent entries", dedup, seeded, recent);
+
+    success(serde_json::json!({ "dedup_by_body": dedup, "seeded": seeded, "avoid_recent": recent, "total": dedup + seeded + recent }))
+}
+
+
+#[post("/generate_random_string")]
+async fn generate_handler(req: HttpRequest, params: web::Json<GenerateParams>, config: web::Data<Config>, recent: web::Data<RecentValues>, body_cache: web::Data<BodyCache>, seeded_cache: web::Data<SeededCache>, metrics: web::Data<Metrics>) -> impl Responder {
     let start = Instant::now();
     let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
-    info!(target: "choose_handler", "Request from: {}", peer);
+    info!(target: "generate_handler", "Request from: {}", peer);
 
-    if params.count == 0 || params.count > MAX_COUNT {
-        let msg = format!("Invalid count: {} (must be 1–{})", params.count, MAX_COUNT);
-        warn!(target: "choose_handler", "{}", msg);
-        return HttpResponse::BadRequest().json(ApiResponse { success: false, data: msg });
-    }
+    generate_response(&params, start, &config, &recent, &body_cache, &seeded_cache, &metrics).await
+}
 
-    if params.count > params.items.len() {
-        let msg = "Count must be <= item count.";
-        warn!(target: "choose_handler", "{}", msg);
-        return HttpResponse::BadRequest().json(ApiResponse { success: false, data: msg.to_string() });
+
+// One entry point for every operation, dispatched on the body's "type"; an unknown type fails deserialization with a 400
+#[post("/generate")]
+async fn typed_generate_handler(req: HttpRequest, params: web::Json<TypedGenerateParams>, config: web::Data<Config>, recent: web::Data<RecentValues>, body_cache: web::Data<BodyCache>, seeded_cache: web::Data<SeededCache>, metrics: web::Data<Metrics>) -> HttpResponse {
+    let start = Instant::now();
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "generate_handler", "Typed request from: {}", peer);
+
+    match params.into_inner() {
+        TypedGenerateParams::String(params) => generate_response(&params, start, &config, &recent, &body_cache, &seeded_cache, &metrics).await,
+        TypedGenerateParams::Choose(params) => choose_response(run_choose(&params, &config), start, &config, &metrics),
     }
+}
 
-    let result = std::panic::catch_unwind(|| {
-        random_module::generate_random_choose(params.items.clone(), params.count)
-    });
+
+async fn generate_response(params: &GenerateParams, start: Instant, config: &Config, recent: &RecentValues, body_cache: &BodyCache, seeded_cache: &SeededCache, metrics: &Metrics) -> HttpResponse {
+    let result = generate_deduplicated(params, config, recent, body_cache, seeded_cache);
+    let duration = start.elapsed();
+    metrics.observe("/generate_random_string", duration, config);
+
+    // Every response, success or error, takes at least min_response_ms so timing reveals nothing about the request
+    if let Some(floor) = config.min_response_ms.map(Duration::from_millis) {
+        sleep(floor.saturating_sub(duration)).await;
+    }
 
     match result {
-        Ok(selected) => {
-            let duration = start.elapsed().as_millis();
-            info!(target: "choose_handler", "Random choice completed in {} ms", duration);
-            HttpResponse::Ok().json(ApiResponse { success: true, data: selected })
+        Ok(generated) => {
+            info!(target: "generate_handler", "Generation completed in {} ms", duration.as_millis());
+            let mut builder = HttpResponse::Ok();
+            if generated.entropy_bits.is_finite() {
+                builder.insert_header((ENTROPY_HEADER, format!("{:.2}", generated.entropy_bits)));
+            }
+            signing::signed_json(builder, &ApiResponse { success: true, data: generated.output })
         }
-        Err(_) => {
-            error!(target: "choose_handler", "Panic occurred during random choose");
-            HttpResponse::InternalServerError().json(ApiResponse { success: false, data: "Internal server error".to_string() })
+        Err(err) => {
+            log_failure!("generate_handler", &err);
+            err.to_response()
         }
     }
 }
 
 
+// Internal errors come from caught panics (or exhausted entropy retries); with retry_on_panic the work gets one more try before the 500
+fn with_panic_retry<T, F>(config: &Config, target: &'static str, run: F) -> Result<T, ServiceError>
+where
+    F: Fn() -> Result<T, ServiceError>,
+{
+    match run() {
+        Err(ServiceError::Internal(msg)) if config.retry_on_panic.unwrap_or(false) => {
+            error!(target: "panic_retry", "{}: {}; retrying once", target, msg);
+            let retried = run();
+            match &retried {
+                Err(ServiceError::Internal(msg)) => error!(target: "panic_retry", "{}: retry failed as well: {}", target, msg),
+                _ => info!(target: "panic_retry", "{}: retry completed without a panic", target),
+            }
+            retried
+        }
+        result => result,
+    }
+}
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let start = Instant::now();
-    let start_data = web::Data::new(start);
-    let config = load_config();
 
-    init_tracing(&config.logs_dir, &config.name_for_port_manager);
+// Identical `dedup_by_body` requests within dedup_ttl_secs get the first result again, absorbing double submits
+fn generate_deduplicated(params: &GenerateParams, config: &Config, recent: &RecentValues, body_cache: &BodyCache, seeded_cache: &SeededCache) -> Result<Generated<GenerateOutput>, ServiceError> {
+    if !params.dedup_by_body.unwrap_or(false) {
+        return generate_seeded_cached(params, config, recent, seeded_cache);
+    }
+
+    let key = serde_json::to_string(params).map_err(|_| ServiceError::Internal("Failed to serialize request for deduplication"))?;
+    let ttl = Duration::from_secs(config.dedup_ttl_secs.unwrap_or(DEFAULT_DEDUP_TTL_SECS));
 
-    let Some(port) = fetch_port(&config).await else {
-        error!(target: "main", "Failed to retrieve port. {} will not start.", &config.name_for_port_manager);
-        std::process::exit(1);
+    let cached = lock(body_cache)
+        .get(&key)
+        .filter(|(stored_at, _)| stored_at.elapsed() < ttl)
+        .map(|(_, generated)| generated.clone());
+
+    if let Some(generated) = cached {
+        info!(target: "generate_handler", "Returning cached result for a repeated body");
+        return Ok(generated);
+    }
+
+    let generated = generate_seeded_cached(params, config, recent, seeded_cache)?;
+    lock(body_cache).insert(key, (Instant::now(), generated.clone()));
+    Ok(generated)
+}
+
+
+// A seeded request always yields the same output, so it is served from cache once seen. Requests whose output
+// also depends on server state (avoid_recent) or on the clock (provenance) are never cached
+fn generate_seeded_cached(params: &GenerateParams, config: &Config, recent: &RecentValues, seeded_cache: &SeededCache) -> Result<Generated<GenerateOutput>, ServiceError> {
+    let cacheable = params.seed.is_some() && !params.avoid_recent.unwrap_or(false) && !params.provenance.unwrap_or(false);
+    if !cacheable {
+        return run_generate(params, config, recent);
+    }
+
+    let key = serde_json::to_string(params).map_err(|_| ServiceError::Internal("Failed to serialize request for caching"))?;
+    let cached = lock(seeded_cache).get(&key).cloned();
+    if let Some(generated) = cached {
+        info!(target: "generate_handler", "Returning cached result for a seeded request");
+        return Ok(generated);
+    }
+
+    let generated = run_generate(params, config, recent)?;
+    lock(seeded_cache).insert(key, generated.clone());
+    Ok(generated)
+}
+
+
+fn run_generate(params: &GenerateParams, config: &Config, recent: &RecentValues) -> Result<Generated<GenerateOutput>, ServiceError> {
+    let length = clamp_to_max("length", params.length, MAX_LENGTH, config);
+    let params = if length == params.length {
+        std::borrow::Cow::Borrowed(params)
+    } else {
+        std::borrow::Cow::Owned(GenerateParams { length, ..params.clone() })
     };
 
-    let ip = get_local_ip().map(|addr| addr.to_string()).unwrap_or("ERROR".to_string());
+    with_panic_retry(config, "generate_handler", || generate_once(&params, config, recent))
+}
 
-    info!(target: "main", "Starting {} on {}:{}", &config.name_for_port_manager, ip, port);
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(start_data.clone())
-            .wrap(LocalNetworkOnly)  
-            .service(status_handler)
-            .service(stop_handler)
-    })
-    .workers(config.workers_count)
-    .bind((ip.as_str(), port))?
-    .run()
-    .await
+// With clamp_count, a `count` or `length` above its maximum is capped there (with a warning) instead of rejected
+fn clamp_to_max(name: &str, value: usize, max: usize, config: &Config) -> usize {
+    if value > max && config.clamp_count.unwrap_or(false) {
+        warn!(target: "clamp_count", "{} {} capped at {}", name, value, max);
+        return max;
+    }
+    value
+}
+
+
+fn generate_once(params: &GenerateParams, config: &Config, recent: &RecentValues) -> Result<Generated<GenerateOutput>, ServiceError> {
+    let min_length = config.min_length.unwrap_or(1).max(1);
+
+    if params.length > MAX_LENGTH {
+        return Err(ServiceError::Invalid(format!("Invalid length: {} (must be {}–{})", params.length, min_length, MAX_LENGTH)));
+    }
+
+    if params.length < min_length {
+        return Err(ServiceError::Invalid(format!("Length too short: {} (minimum is {})", params.length, min_length)));
+    }
+
+    for (name, affix) in [("prefix", &params.prefix), ("suffix", &params.suffix)] {
+        if affix.as_ref().is_some_and(|affix| affix.chars().count() > MAX_LENGTH) {
+            return Err(ServiceError::Invalid(format!("{} is too long (maximum is {} characters)", name, MAX_LENGTH)));
+        }
+    }
+
+    if let Some(unknown) = params.encodings.iter().flatten().find(|name| !ENCODINGS.contains(&name.as_str())) {
+        return Err(ServiceError::Invalid(format!("Unknown encoding: {:?} (expected one of {})", unknown, ENCODINGS.join(", "))));
+    }
+
+    if params.crockford_check.unwrap_or(false) && params.charset_preset.as_deref() != Some("crockford_base32") {
+        return Err(ServiceError::Invalid("crockford_check requires charset_preset \"crockford_base32\".".to_string()));
+    }
+
+    // Drawn from a fresh RNG so failure injection never disturbs a seeded stream
+    if let Some(rate) = config.chaos_error_rate.filter(|&rate| rate > 0.0) && os_rng()?.chance(rate) {
+        return Err(ServiceError::Injected);
+    }
+
+    let (mut rng, stream) = request_rng(params)?;
+
+    let avoid_recent = params.avoid_recent.unwrap_or(false);
+    let mut attempts = 0;
+
+    let (value, entropy_bits) = loop {
+        let (value, entropy_bits) = match &params.syllable_pattern {
+            Some(pattern) => generate_syllables(params, pattern, &mut rng)?,
+            None if params.luhn_valid.unwrap_or(false) => generate_luhn(params, &mut rng)?,
+            None => match params.charset_preset.as_deref().and_then(random_module::script_alphabet) {
+                Some(alphabet) => generate_script(params, alphabet, config, &mut rng)?,
+                None => generate_value(params, config, &mut rng)?,
+            },
+        };
+        attempts += 1;
+
+        // Best effort only: after MAX_RECENT_RETRIES collisions the last candidate is returned anyway
+        if !avoid_recent || attempts >= MAX_RECENT_RETRIES || !lock(recent).contains(&value) {
+            break (value, entropy_bits);
+        }
+    };
+
+    if avoid_recent {
+        lock(recent).insert(value.clone(), ());
+    }
+
+    let class_breakdown = params.class_breakdown.unwrap_or(false).then(|| ClassBreakdown::of(&value));
+
+    // Like the affixes, the check symbol is not random and not counted in `length`
+    let value = if params.crockford_check.unwrap_or(false) {
+        let check = random_module::crockford_check_symbol(&value);
+        format!("{}{}", value, check)
+    } else {
+        value
+    };
+
+    // Fixed affixes carry no randomness: `length`, the policy, the breakdown and the entropy all refer to the middle only
+    let value = format!("{}{}{}", params.prefix.as_deref().unwrap_or_default(), value, params.suffix.as_deref().unwrap_or_default());
+
+    let exported = stream.filter(|_| params.export_seed.unwrap_or(false));
+    let encodings = params.encodings.as_ref().map(|names| encode_value(&value, names));
+    let provenance = params.provenance.unwrap_or(false).then(|| Provenance {
+        algorithm: random_module::ALGORITHM,
+        crate_version: env!("CARGO_PKG_VERSION"),
+        generated_at: chrono::Utc::now().to_rfc3339(),
+    });
+    let output = if params.policy.is_none() && exported.is_none() && encodings.is_none() && provenance.is_none() && class_breakdown.is_none() {
+        GenerateOutput::Plain(value)
+    } else {
+        GenerateOutput::Detailed(Box::new(StringResult {
+            value,
+            policy: params.policy.clone(),
+            seed: exported.map(|(seed, _)| seed),
+            counter: exported.map(|(_, counter)| counter),
+            encodings,
+            provenance,
+            class_breakdown,
+        }))
+    };
+
+    Ok(Generated { output, entropy_bits })
+}
+
+
+// The value's UTF-8 bytes rendered in each requested encoding; names are validated up front
+fn encode_value(value: &str, names: &[String]) -> BTreeMap<String, String> {
+    use base64::Engine;
+
+    names
+        .iter()
+        .map(|name| {
+            let encoded = match name.as_str() {
+                "hex" => hex::encode(value),
+                "base64" => base64::engine::general_purpose::STANDARD.encode(value),
+                _ => value.to_string(),
+            };
+            (name.clone(), encoded)
+        })
+        .collect()
+}
+
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+
+fn generate_value(params: &GenerateParams, config: &Config, rng: &mut SecureRandom) -> Result<(String, f64), ServiceError> {
+    let charset = filtered_charset(params, rng)?;
+    let mut letters = Vec::new();
+
+    if params.identifier_safe.unwrap_or(false) {
+        letters = charset.iter().copied().filter(u8::is_ascii_alphabetic).collect();
+        if letters.is_empty() {
+            return Err(ServiceError::Invalid("identifier_safe requires lowercase or uppercase letters to be enabled.".to_string()));
+        }
+    }
+
+    // A charset that collapses to one character would yield a constant string, which is almost certainly a mistake
+    let min_charset_size = config.min_charset_size.unwrap_or(DEFAULT_MIN_CHARSET_SIZE);
+    let distinct = charset.iter().collect::<std::collections::HashSet<_>>().len();
+    if distinct < min_charset_size {
+        return Err(ServiceError::Invalid(format!(
+            "Degenerate charset: {} distinct character(s) after filtering, at least {} required",
+            distinct, min_charset_size
+        )));
+    }
+
+    let mut pools: Vec<&[u8]> = vec![&charset; params.length];
+    if !letters.is_empty() {
+        pools[0] = &letters;
+    }
+
+    let constrained = constrain_positions(params, &pools)?;
+    for (pool, narrowed) in pools.iter_mut().zip(&constrained) {
+        if let Some(narrowed) = narrowed {
+            *pool = narrowed;
+        }
+    }
+
+    let entropy_bits = random_module::positional_entropy_bits(&pools);
+
+    // Checked up front: a string cannot hold more distinct characters than its length or its charset
+    let min_unique = params.min_unique_chars.unwrap_or(0);
+    if min_unique > params.length.min(distinct) {
+        return Err(ServiceError::Invalid(format!(
+            "min_unique_chars {} is impossible with length {} and {} distinct character(s)",
+            min_unique, params.length, distinct
+        )));
+    }
+
+    let value = if params.policy.is_some() || min_unique > 1 {
+        generate_with_policy(params, params.policy.as_ref(), &charset, &pools, rng)?
+    } else {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            random_module::generate_positional(rng, &pools)
+        }))
+        .map_err(|_| ServiceError::Internal("Panic occurred during string generation"))?
+    };
+
+    Ok((value, entropy_bits))
+}
+
+
+// Narrowed pools for the positions covered by `position_constraints`; overlapping rules intersect
+fn constrain_positions(params: &GenerateParams, pools: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, ServiceError> {
+    let mut constrained: Vec<Option<Vec<u8>>> = vec![None; pools.len()];
+
+    for rule in params.position_constraints.iter().flatten() {
+        let end = rule.end.unwrap_or(rule.start);
+        if rule.start > end || end >= params.length {
+            return Err(ServiceError::Invalid(format!(
+                "Invalid position range {}–{} for length {}",
+                rule.start, end, params.length
+            )));
+        }
+
+        for position in rule.start..=end {
+            let mut pool = constrained[position].take().unwrap_or_else(|| pools[position].to_vec());
+            pool.retain(|&b| rule.classes.contains(&CharClass::of(b as char)));
+            if pool.is_empty() {
+                return Err(ServiceError::Invalid(format!(
+                    "No enabled characters satisfy the constraints on position {}",
+                    position
+                )));
+            }
+            constrained[position] = Some(pool);
+        }
+    }
+
+    Ok(constrained)
+}
+
+
+// Syllable mode ignores the charset flags: output follows the C/V template, repeated up to `length`
+fn generate_syllables(params: &GenerateParams, pattern: &str, rng: &mut SecureRandom) -> Result<(String, f64), ServiceError> {
+    if pattern.is_empty() || !pattern.chars().all(|c| c == 'C' || c == 'V') {
+        return Err(ServiceError::Invalid(format!("Invalid syllable_pattern: {:?} (use only 'C' and 'V')", pattern)));
+    }
+
+    if params.policy.is_some() || params.max_classes.is_some() || params.position_constraints.is_some() || params.luhn_valid.unwrap_or(false) || params.denylist.is_some() || params.min_unique_chars.is_some() {
+        return Err(ServiceError::Invalid("syllable_pattern cannot be combined with policy, max_classes, position_constraints, luhn_valid, denylist or min_unique_chars.".to_string()));
+    }
+
+    let value = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        random_module::generate_from_syllables(rng, pattern, params.length)
+    }))
+    .map_err(|_| ServiceError::Internal("Panic occurred during syllable generation"))?;
+
+    Ok((value, random_module::syllable_entropy_bits(pattern, params.length)))
+}
+
+
+// Luhn mode ignores the charset flags: random digits plus a check digit, so only `length - 1` positions carry entropy
+fn generate_luhn(params: &GenerateParams, rng: &mut SecureRandom) -> Result<(String, f64), ServiceError> {
+    if params.length < 2 {
+        return Err(ServiceError::Invalid("luhn_valid requires a length of at least 2.".to_string()));
+    }
+
+    if params.policy.is_some() || params.max_classes.is_some() || params.position_constraints.is_some() || params.charset_preset.is_some() || params.denylist.is_some() || params.min_unique_chars.is_some() {
+        return Err(ServiceError::Invalid("luhn_valid cannot be combined with policy, max_classes, position_constraints, charset_preset, denylist or min_unique_chars.".to_string()));
+    }
+
+    let value = random_module::generate_luhn(rng, params.length);
+    Ok((value, (params.length - 1) as f64 * 10f64.log2()))
+}
+
+
+// Script presets (cyrillic, greek, ...) are chars, not bytes, so only the options that work per character apply
+fn generate_script(params: &GenerateParams, mut alphabet: Vec<char>, config: &Config, rng: &mut SecureRandom) -> Result<(String, f64), ServiceError> {
+    if params.policy.is_some() || params.max_classes.is_some() || params.position_constraints.is_some() || params.identifier_safe.unwrap_or(false) || params.min_unique_chars.is_some() {
+        return Err(ServiceError::Invalid("Script presets cannot be combined with policy, max_classes, position_constraints, identifier_safe or min_unique_chars.".to_string()));
+    }
+
+    if let Some(denylist) = &params.denylist {
+        alphabet.retain(|c| !denylist.contains(*c));
+    }
+
+    let min_charset_size = config.min_charset_size.unwrap_or(DEFAULT_MIN_CHARSET_SIZE);
+    if alphabet.len() < min_charset_size {
+        return Err(ServiceError::Invalid(format!(
+            "Degenerate charset: {} distinct character(s) after filtering, at least {} required",
+            alphabet.len(), min_charset_size
+        )));
+    }
+
+    let value = random_module::generate_from_alphabet(rng, &alphabet, params.length);
+    Ok((value, params.length as f64 * (alphabet.len() as f64).log2()))
+}
+
+
+// Seeded requests (or ones asking for seed export) draw from a reproducible
+// (seed, counter) ChaCha stream; everything else uses a fresh OS-seeded RNG
+fn request_rng(params: &GenerateParams) -> Result<(SecureRandom, Option<(u64, u64)>), ServiceError> {
+    if params.seed.is_none() && !params.export_seed.unwrap_or(false) {
+        return Ok((os_rng()?, None));
+    }
+
+    let seed = match params.seed {
+        Some(seed) => seed,
+        None => random_module::try_random_seed()?,
+    };
+    let counter = params.counter.unwrap_or(0);
+    Ok((SecureRandom::from_seed_stream(seed, counter), Some((seed, counter))))
+}
+
+
+// A fresh RNG for one request; running out of entropy_retries is a 500 rather than a panic
+fn os_rng() -> Result<SecureRandom, ServiceError> {
+    Ok(SecureRandom::try_new()?)
+}
+
+
+// The whole pool generate_value draws from; /charset_info reports the same
+fn filtered_charset(params: &GenerateParams, rng: &mut SecureRandom) -> Result<Vec<u8>, ServiceError> {
+    if params.charset_preset.is_none() && !(params.use_digits || params.use_lowercase || params.use_uppercase || params.use_spec) {
+        return Err(ServiceError::Invalid("At least one charset must be enabled (digits, lowercase, uppercase, special).".to_string()));
+    }
+
+    let mut charset = resolve_charset(params, rng)?;
+    if params.identifier_safe.unwrap_or(false) {
+        charset.retain(|&b| b.is_ascii_alphanumeric() || b == b'_');
+    }
+
+    Ok(charset)
+}
+
+
+fn resolve_charset(params: &GenerateParams, rng: &mut SecureRandom) -> Result<Vec<u8>, ServiceError> {
+    let charset = match &params.charset_preset {
+        Some(name) => random_module::preset_charset(name)
+            .ok_or_else(|| ServiceError::Invalid(format!("Unknown charset_preset: {:?} (expected one of {})", name, random_module::PRESET_NAMES.join(", "))))?,
+        None => random_module::build_charset(params.use_digits, params.use_lowercase, params.use_uppercase, params.use_spec),
+    };
+
+    let charset = match &params.denylist {
+        Some(denylist) => {
+            let allowed: Vec<u8> = charset.into_iter().filter(|&b| !denylist.contains(b as char)).collect();
+            if allowed.is_empty() {
+                return Err(ServiceError::Invalid("denylist removes every character of the charset.".to_string()));
+            }
+            allowed
+        }
+        None => charset,
+    };
+
+    let Some(max_classes) = params.max_classes else {
+        return Ok(charset);
+    };
+
+    if max_classes == 0 {
+        return Err(ServiceError::Invalid("max_classes must be at least 1".to_string()));
+    }
+
+    let required = params.policy.as_ref().map(PasswordPolicy::required_classes).unwrap_or_default();
+    if required.len() > max_classes {
+        return Err(ServiceError::Invalid(format!(
+            "Policy requires {} character classes but max_classes is {}",
+            required.len(),
+            max_classes
+        )));
+    }
+
+    Ok(random_module::limit_classes(rng, &charset, max_classes, &required))
+}
+
+
+// Rejection sampling against the policy and/or min_unique_chars, bounded by MAX_POLICY_ATTEMPTS
+fn generate_with_policy(params: &GenerateParams, policy: Option<&PasswordPolicy>, charset: &[u8], pools: &[&[u8]], rng: &mut SecureRandom) -> Result<String, ServiceError> {
+    if let Some(Err(reason)) = policy.map(|policy| policy.validate(charset, params.length)) {
+        return Err(ServiceError::Invalid(format!("Unsatisfiable policy: {}", reason)));
+    }
+
+    let min_unique = params.min_unique_chars.unwrap_or(0);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        random_module::generate_positional_matching(
+            rng,
+            pools,
+            |candidate| {
+                policy.is_none_or(|policy| policy.is_satisfied_by(candidate))
+                    && candidate.bytes().collect::<std::collections::HashSet<_>>().len() >= min_unique
+            },
+            MAX_POLICY_ATTEMPTS,
+        )
+    }));
+
+    match result {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) if policy.is_some() => Err(ServiceError::Invalid(format!(
+            "Policy not satisfied after {} attempts; increase length or relax the policy.",
+            MAX_POLICY_ATTEMPTS
+        ))),
+        Ok(None) => Err(ServiceError::Invalid(format!(
+            "min_unique_chars not reached after {} attempts; increase length or widen the charset.",
+            MAX_POLICY_ATTEMPTS
+        ))),
+        Err(_) => Err(ServiceError::Internal("Panic occurred during policy string generation")),
+    }
+}
+
+
+#[post("/generate_random_choose")]
+async fn choose_handler(req: HttpRequest, params: web::Json<ChooseParams<Value>>, config: web::Data<Config>, metrics: web::Data<Metrics>) -> impl Responder {
+    let start = Instant::now();
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "choose_handler", "Request from: {}", peer);
+
+    choose_response(run_choose(&params, &config), start, &config, &metrics)
+}
+
+
+fn is_plain_text(ctx: &guard::GuardContext) -> bool {
+    ctx.header::<header::ContentType>().is_some_and(|content_type| content_type.essence_str() == "text/plain")
+}
+
+
+// Same endpoint for shell pipelines: every non-empty line of the body is one string item
+#[post("/generate_random_choose", guard = "is_plain_text")]
+async fn choose_text_handler(req: HttpRequest, query: web::Query<TextChooseQuery>, body: String, config: web::Data<Config>, metrics: web::Data<Metrics>) -> impl Responder {
+    let start = Instant::now();
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "choose_handler", "Plain-text request from: {}", peer);
+
+    let params = ChooseParams {
+        items: body.lines().map(str::trim_end).filter(|line| !line.is_empty()).map(Value::from).collect(),
+        count: query.count.unwrap_or(1),
+        dedup_normalized: query.dedup_normalized,
+        weight_by_length: query.weight_by_length,
+        ..Default::default()
+    };
+
+    choose_response(run_choose(&params, &config), start, &config, &metrics)
+}
+
+
+fn is_ndjson(ctx: &guard::GuardContext) -> bool {
+    ctx.header::<header::ContentType>().is_some_and(|content_type| content_type.essence_str() == "application/x-ndjson")
+}
+
+
+// Same endpoint for candidate sets too large to buffer: one JSON item per line, sampled while streaming
+#[post("/generate_random_choose", guard = "is_ndjson")]
+async fn choose_stream_handler(req: HttpRequest, query: web::Query<StreamChooseQuery>, payload: web::Payload, config: web::Data<Config>, metrics: web::Data<Metrics>) -> impl Responder {
+    let start = Instant::now();
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "choose_handler", "NDJSON request from: {}", peer);
+
+    let declared_length = req.headers().get(header::CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.trim().parse().ok());
+    choose_response(run_choose_stream(payload, declared_length, query.count.unwrap_or(1), &config).await, start, &config, &metrics)
+}
+
+
+async fn run_choose_stream(mut payload: web::Payload, declared_length: Option<usize>, count: usize, config: &Config) -> Result<Generated<Vec<Value>>, ServiceError> {
+    let count = clamp_to_max("count", count, MAX_COUNT, config);
+    if count == 0 || count > MAX_COUNT {
+        return Err(ServiceError::Invalid(format!("Invalid count: {} (must be 1–{})", count, MAX_COUNT)));
+    }
+
+    let max_depth = config.max_json_depth.unwrap_or(DEFAULT_MAX_JSON_DEPTH);
+    let max_bytes = config.max_stream_bytes.unwrap_or(DEFAULT_MAX_STREAM_BYTES);
+    let too_large = || ServiceError::TooLarge(format!("Request body exceeds {} bytes", max_bytes));
+    if declared_length.is_some_and(|length| length > max_bytes) {
+        return Err(too_large());
+    }
+
+    let mut reservoir = random_module::Reservoir::new(os_rng()?, count);
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line_number = 0;
+    let mut received = 0;
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| ServiceError::Invalid(format!("Failed to read request body: {}", e)))?;
+        // Chunked bodies declare no length, so the limit is also enforced on what actually arrives
+        received += chunk.len();
+        if received > max_bytes {
+            return Err(too_large());
+        }
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            line_number += 1;
+            offer_ndjson_line(&line, line_number, max_depth, &mut reservoir)?;
+            check_item_count(reservoir.seen(), config)?;
+        }
+    }
+    offer_ndjson_line(&buffer, line_number + 1, max_depth, &mut reservoir)?;
+    check_item_count(reservoir.seen(), config)?;
+
+    let seen = reservoir.seen();
+    if count > seen {
+        return Err(ServiceError::Invalid("Count must be <= item count.".to_string()));
+    }
+
+    Ok(Generated {
+        output: reservoir.into_items(),
+        entropy_bits: random_module::choose_entropy_bits(seen, count),
+    })
+}
+
+
+// Separate from max_body_bytes: many tiny items fit in a small body but still cost a pass each
+fn check_item_count(len: usize, config: &Config) -> Result<(), ServiceError> {
+    match config.max_items {
+        Some(max_items) if len > max_items => Err(ServiceError::Invalid(format!("Too many items: more than max_items ({})", max_items))),
+        _ => Ok(()),
+    }
+}
+
+
+fn offer_ndjson_line(line: &[u8], line_number: usize, max_depth: usize, reservoir: &mut random_module::Reservoir<Value>) -> Result<(), ServiceError> {
+    let line = line.trim_ascii();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let item: Value = serde_json::from_slice(line)
+        .map_err(|e| ServiceError::Invalid(format!("Line {}: invalid JSON ({})", line_number, e)))?;
+
+    if json_depth(&item) > max_depth {
+        return Err(ServiceError::Invalid(format!("Line {} exceeds the maximum JSON depth of {}.", line_number, max_depth)));
+    }
+
+    reservoir.offer(item);
+    Ok(())
+}
+
+
+fn choose_response<T: Serialize>(result: Result<Generated<T>, ServiceError>, start: Instant, config: &Config, metrics: &Metrics) -> HttpResponse {
+    let duration = start.elapsed();
+    metrics.observe("/generate_random_choose", duration, config);
+
+    match result {
+        Ok(generated) => {
+            info!(target: "choose_handler", "Random choice completed in {} ms", duration.as_millis());
+            let mut builder = HttpResponse::Ok();
+            if generated.entropy_bits.is_finite() {
+                builder.insert_header((ENTROPY_HEADER, format!("{:.2}", generated.entropy_bits)));
+            }
+            signing::signed_json(builder, &ApiResponse { success: true, data: generated.output })
+        }
+        Err(err) => {
+            log_failure!("choose_handler", &err);
+            err.to_response()
+        }
+    }
+}
+
+
+fn run_choose(params: &ChooseParams<Value>, config: &Config) -> Result<Generated<Value>, ServiceError> {
+    let count = clamp_to_max("count", params.count, MAX_COUNT, config);
+    let params = if count == params.count {
+        std::borrow::Cow::Borrowed(params)
+    } else {
+        std::borrow::Cow::Owned(ChooseParams { count, ..params.clone() })
+    };
+
+    if params.join.is_some() && params.items.iter().any(|item| !item.is_string()) {
+        return Err(ServiceError::Invalid("join requires every item to be a string.".to_string()));
+    }
+
+    let ranked = params.ranked.unwrap_or(false);
+    if ranked && (params.join.is_some() || params.strata_key.is_some()) {
+        return Err(ServiceError::Invalid("ranked cannot be combined with join or strata_key.".to_string()));
+    }
+
+    let mut generated = with_panic_retry(config, "choose_handler", || choose_once(&params, config))?;
+
+    // Equal picks (possible when the input repeats a value) collapse to the first one, so fewer than `count` may come back
+    if params.distinct_output.unwrap_or(false) {
+        let mut seen = Vec::new();
+        generated.output.retain(|item| {
+            let fresh = !seen.contains(item);
+            if fresh {
+                seen.push(item.clone());
+            }
+            fresh
+        });
+    }
+
+    let output = match &params.join {
+        // For consumers storing the pick in a single text column
+        Some(separator) => Value::from(generated.output.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(separator)),
+        // Picks already come out in draw order (stratified ones excepted); ranked only makes the position explicit
+        None if ranked => generated.output.into_iter()
+            .enumerate()
+            .map(|(index, item)| serde_json::json!({ "rank": index + 1, "item": item }))
+            .collect(),
+        None => Value::from(generated.output),
+    };
+
+    Ok(Generated { output, entropy_bits: generated.entropy_bits })
+}
+
+
+fn choose_once(params: &ChooseParams<Value>, config: &Config) -> Result<Generated<Vec<Value>>, ServiceError> {
+    if params.count == 0 || params.count > MAX_COUNT {
+        return Err(ServiceError::Invalid(format!("Invalid count: {} (must be 1–{})", params.count, MAX_COUNT)));
+    }
+
+    check_item_count(params.items.len(), config)?;
+
+    let max_depth = config.max_json_depth.unwrap_or(DEFAULT_MAX_JSON_DEPTH);
+    if let Some(index) = params.items.iter().position(|item| json_depth(item) > max_depth) {
+        return Err(ServiceError::Invalid(format!("Item {} exceeds the maximum JSON depth of {}.", index, max_depth)));
+    }
+
+    let dedup = params.dedup_normalized.unwrap_or(false);
+    let candidates = if dedup { dedup_normalized(&params.items) } else { params.items.clone() };
+
+    if params.count > candidates.len() {
+        let msg = if dedup { "Count must be <= distinct item count." } else { "Count must be <= item count." };
+        return Err(ServiceError::Invalid(msg.to_string()));
+    }
+
+    let by_length = params.weight_by_length.unwrap_or(false);
+    let inverse_frequency = params.inverse_frequency.unwrap_or(false);
+    let base_weightings = [by_length, params.weights_map.is_some(), params.weight_pointer.is_some(), inverse_frequency].iter().filter(|&&set| set).count();
+    if base_weightings > 1 {
+        return Err(ServiceError::Invalid("weights_map, weight_by_length, weight_pointer and inverse_frequency cannot be combined.".to_string()));
+    }
+    let weighted = base_weightings > 0 || params.recency_key.is_some();
+
+    if let Some(key) = &params.shuffle_key {
+        if weighted || params.strata_key.is_some() {
+            return Err(ServiceError::Invalid("shuffle_key cannot be combined with weighting or strata_key.".to_string()));
+        }
+        return choose_keyed(candidates, key, params.count);
+    }
+
+    if let Some(key) = &params.strata_key {
+        if weighted {
+            return Err(ServiceError::Invalid("strata_key cannot be combined with weighting.".to_string()));
+        }
+        return choose_stratified(&candidates, key, params.count);
+    }
+
+    let mut weights: Option<Vec<f64>> = if by_length {
+        Some(candidates.iter().map(|item| item_key(item).chars().count() as f64).collect())
+    } else if let Some(pointer) = &params.weight_pointer {
+        Some(pointer_weights(&candidates, pointer)?)
+    } else if inverse_frequency {
+        Some(inverse_frequency_weights(&candidates))
+    } else {
+        params.weights_map.as_ref().map(|weights_map| {
+            candidates.iter().map(|item| weights_map.get(item_key(item).as_ref()).copied().unwrap_or(1.0)).collect()
+        })
+    };
+
+    // Recency decay multiplies whatever base weights apply (1.0 each without any)
+    match (&params.recency_key, params.half_life_secs) {
+        (Some(key), Some(half_life)) => {
+            let decay = recency_decay(&candidates, key, half_life)?;
+            weights = Some(match weights {
+                Some(base) => base.iter().zip(decay).map(|(base, decay)| base * decay).collect(),
+                None => decay,
+            });
+        }
+        (Some(_), None) => return Err(ServiceError::Invalid("recency_key requires half_life_secs.".to_string())),
+        (None, Some(_)) => return Err(ServiceError::Invalid("half_life_secs requires recency_key.".to_string())),
+        (None, None) => {}
+    }
+
+    if let Some(weights) = weights {
+        return choose_weighted(&candidates, &weights, params.count);
+    }
+
+    let entropy_bits = random_module::choose_entropy_bits(candidates.len(), params.count);
+    let mut rng = os_rng()?;
+
+    std::panic::catch_unwind(move || {
+        random_module::generate_random_choose(&mut rng, candidates, params.count)
+    })
+    .map(|output| Generated { output, entropy_bits })
+    .map_err(|_| ServiceError::Internal("Panic occurred during random choose"))
+}
+
+
+// Deterministic order by HMAC-SHA256(key, item key): every service holding the key derives the same permutation.
+// The sort is stable, so items with equal keys keep their input order
+fn choose_keyed(candidates: Vec<Value>, key: &str, count: usize) -> Result<Generated<Vec<Value>>, ServiceError> {
+    if key.is_empty() {
+        return Err(ServiceError::Invalid("shuffle_key must not be empty.".to_string()));
+    }
+
+    let mut ranked: Vec<(Vec<u8>, Value)> = candidates
+        .into_iter()
+        .map(|item| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+            mac.update(item_key(&item).as_bytes());
+            (mac.finalize().into_bytes().to_vec(), item)
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // Nothing is drawn at request time, so the output carries no fresh entropy
+    Ok(Generated {
+        output: ranked.into_iter().take(count).map(|(_, item)| item).collect(),
+        entropy_bits: 0.0,
+    })
+}
+
+
+// Each item's weight is the number found at the JSON Pointer (RFC 6901), e.g. "/meta/score"
+fn pointer_weights(candidates: &[Value], pointer: &str) -> Result<Vec<f64>, ServiceError> {
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            item.pointer(pointer)
+                .and_then(Value::as_f64)
+                .ok_or_else(|| ServiceError::Invalid(format!("Item {} has no number at weight_pointer {:?}.", index, pointer)))
+        })
+        .collect()
+}
+
+
+// Each copy of a value weighs 1/copies, so every distinct value (by item key) carries the same total weight
+fn inverse_frequency_weights(candidates: &[Value]) -> Vec<f64> {
+    let mut frequency: HashMap<std::borrow::Cow<'_, str>, usize> = HashMap::new();
+    for item in candidates {
+        *frequency.entry(item_key(item)).or_insert(0) += 1;
+    }
+
+    candidates.iter().map(|item| 1.0 / frequency[&item_key(item)] as f64).collect()
+}
+
+
+// Per-item factor 0.5^(age / half_life), age measured from the item's `key` timestamp (Unix seconds or RFC 3339)
+// to now; timestamps in the future count as age 0
+fn recency_decay(candidates: &[Value], key: &str, half_life: f64) -> Result<Vec<f64>, ServiceError> {
+    if !half_life.is_finite() || half_life <= 0.0 {
+        return Err(ServiceError::Invalid(format!("Invalid half_life_secs: {} (must be finite and > 0)", half_life)));
+    }
+
+    let now = chrono::Utc::now();
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let timestamp = match item.get(key) {
+                Some(Value::Number(secs)) => secs.as_f64(),
+                Some(Value::String(text)) => chrono::DateTime::parse_from_rfc3339(text).ok().map(|at| at.timestamp_millis() as f64 / 1000.0),
+                _ => None,
+            }
+            .ok_or_else(|| ServiceError::Invalid(format!("Item {} has no {:?} timestamp (Unix seconds or RFC 3339).", index, key)))?;
+
+            let age = (now.timestamp_millis() as f64 / 1000.0 - timestamp).max(0.0);
+            Ok(0.5f64.powf(age / half_life))
+        })
+        .collect()
+}
+
+
+// Returns how many items have a positive weight
+fn validate_weights(weights: &[f64]) -> Result<usize, ServiceError> {
+    if let Some(bad) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+        return Err(ServiceError::Invalid(format!("Invalid weight: {} (weights must be finite and >= 0)", bad)));
+    }
+
+    // Finite weights can still overflow when summed, which would turn the sampling probabilities into NaN
+    if !weights.iter().sum::<f64>().is_finite() {
+        return Err(ServiceError::Invalid("Weights are too large: their sum is not a finite number.".to_string()));
+    }
+
+    Ok(weights.iter().filter(|w| **w > 0.0).count())
+}
+
+
+fn choose_weighted(candidates: &[Value], weights: &[f64], count: usize) -> Result<Generated<Vec<Value>>, ServiceError> {
+    let positive = validate_weights(weights)?;
+    if count > positive {
+        return Err(ServiceError::Invalid(format!("Count must be <= number of items with positive weight ({}).", positive)));
+    }
+
+    // Uniform bound over the eligible items; weighting can only lower the real entropy
+    let entropy_bits = random_module::choose_entropy_bits(positive, count);
+    let mut rng = os_rng()?;
+
+    std::panic::catch_unwind(move || {
+        random_module::generate_weighted_choose(&mut rng, candidates, weights, count)
+    })
+    .map(|output| Generated { output, entropy_bits })
+    .map_err(|_| ServiceError::Internal("Panic occurred during weighted random choose"))
+}
+
+
+// Runs the generation policy engine against a client-supplied value; the value itself is never logged
+#[post("/check_policy")]
+async fn check_policy_handler(req: HttpRequest, params: web::Json<CheckPolicyParams>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "check_policy_handler", "Request from: {}", peer);
+
+    let rules = params.policy.check(&params.value);
+    let passed = rules.iter().all(|rule| rule.passed);
+
+    success(PolicyReport { passed, rules })
+}
+
+
+// Lets UIs show the exact pool a /generate_random_string request with the same options would draw from
+#[get("/charset_info")]
+async fn charset_info_handler(req: HttpRequest, query: web::Query<CharsetInfoQuery>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "charset_info_handler", "Request from: {}", peer);
+
+    let query = query.into_inner();
+    let params = GenerateParams {
+        use_digits: query.use_digits,
+        use_lowercase: query.use_lowercase,
+        use_uppercase: query.use_uppercase,
+        use_spec: query.use_spec,
+        charset_preset: query.charset_preset,
+        denylist: query.denylist,
+        identifier_safe: query.identifier_safe,
+        ..Default::default()
+    };
+
+    match charset_info(&params) {
+        Ok(info) => success(info),
+        Err(err) => {
+            if let ServiceError::Invalid(msg) | ServiceError::TooLarge(msg) = &err {
+                warn!(target: "charset_info_handler", "{}", msg);
+            }
+            err.to_response()
+        }
+    }
+}
+
+
+fn charset_info(params: &GenerateParams) -> Result<CharsetInfo, ServiceError> {
+    let (base, charset): (Vec<char>, Vec<char>) = match params.charset_preset.as_deref().and_then(random_module::script_alphabet) {
+        Some(alphabet) => {
+            if params.identifier_safe.unwrap_or(false) {
+                return Err(ServiceError::Invalid("Script presets cannot be combined with identifier_safe.".to_string()));
+            }
+            let denylist = params.denylist.as_deref().unwrap_or_default();
+            let charset = alphabet.iter().copied().filter(|&c| !denylist.contains(c)).collect();
+            (alphabet, charset)
+        }
+        None => {
+            let base = match &params.charset_preset {
+                Some(name) => random_module::preset_charset(name),
+                None => Some(random_module::build_charset(params.use_digits, params.use_lowercase, params.use_uppercase, params.use_spec)),
+            };
+            // filtered_charset reports an unknown preset or an empty selection
+            let charset = filtered_charset(params, &mut os_rng()?)?;
+            (base.unwrap_or_default().into_iter().map(char::from).collect(), charset.into_iter().map(char::from).collect())
+        }
+    };
+
+    Ok(CharsetInfo {
+        size: charset.len(),
+        excluded: base.iter().filter(|c| !charset.contains(c)).collect(),
+        charset: charset.into_iter().collect(),
+    })
+}
+
+
+#[post("/choose_many")]
+async fn choose_many_handler(req: HttpRequest, params: web::Json<ChooseManyParams>, config: web::Data<Config>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "choose_many_handler", "Request from: {}", peer);
+
+    match run_choose_many(&params, &config) {
+        Ok(draws) => success(draws),
+        Err(err) => {
+            log_failure!("choose_many_handler", &err);
+            err.to_response()
+        }
+    }
+}
+
+
+#[post("/generate_coords")]
+async fn coords_handler(req: HttpRequest, params: web::Json<CoordsParams>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "coords_handler", "Request from: {}", peer);
+
+    match run_coords(&params) {
+        Ok(points) => success(points),
+        Err(err) => {
+            log_failure!("coords_handler", &err);
+            err.to_response()
+        }
+    }
+}
+
+
+fn run_coords(params: &CoordsParams) -> Result<Vec<Coordinate>, ServiceError> {
+    if params.count == 0 || params.count > MAX_COORDS {
+        return Err(ServiceError::Invalid(format!("Invalid count: {} (must be 1–{})", params.count, MAX_COORDS)));
+    }
+
+    for (name, min, max, limit) in [("lat", params.min_lat, params.max_lat, 90.0), ("lon", params.min_lon, params.max_lon, 180.0)] {
+        // NaN fails the range checks as well
+        if !(-limit..=limit).contains(&min) || !(-limit..=limit).contains(&max) {
+            return Err(ServiceError::Invalid(format!("min_{0} and max_{0} must be within -{1}–{1}", name, limit)));
+        }
+        if min >= max {
+            return Err(ServiceError::Invalid(format!("min_{0} must be less than max_{0}", name)));
+        }
+    }
+
+    let mut rng = os_rng()?;
+    let points = std::panic::catch_unwind(move || {
+        random_module::generate_random_coords(&mut rng, params.min_lat, params.max_lat, params.min_lon, params.max_lon, params.count, params.on_sphere.unwrap_or(false))
+    })
+    .map_err(|_| ServiceError::Internal("Panic occurred during coordinate generation"))?;
+
+    Ok(points.into_iter().map(|(lat, lon)| Coordinate { lat, lon }).collect())
+}
+
+
+#[post("/partial_permutation")]
+async fn partial_permutation_handler(req: HttpRequest, params: web::Json<PartialPermutationParams>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "partial_permutation_handler", "Request from: {}", peer);
+
+    match run_partial_permutation(&params) {
+        Ok(values) => success(values),
+        Err(err) => {
+            log_failure!("partial_permutation_handler", &err);
+            err.to_response()
+        }
+    }
+}
+
+
+fn run_partial_permutation(params: &PartialPermutationParams) -> Result<Vec<u64>, ServiceError> {
+    if params.k == 0 || params.k > MAX_PERMUTATION_K {
+        return Err(ServiceError::Invalid(format!("Invalid k: {} (must be 1–{})", params.k, MAX_PERMUTATION_K)));
+    }
+
+    if params.k as u64 > params.n {
+        return Err(ServiceError::Invalid("k must be <= n.".to_string()));
+    }
+
+    let mut rng = os_rng()?;
+    std::panic::catch_unwind(move || random_module::partial_permutation(&mut rng, params.n, params.k))
+        .map_err(|_| ServiceError::Internal("Panic occurred during partial permutation"))
+}
+
+
+// Independent draws from one pool; each draw is `count_per_draw` distinct picks, like one /generate_random_choose call
+fn run_choose_many(params: &ChooseManyParams, config: &Config) -> Result<Vec<Vec<Value>>, ServiceError> {
+    if params.count_per_draw == 0 || params.count_per_draw > MAX_COUNT {
+        return Err(ServiceError::Invalid(format!("Invalid count_per_draw: {} (must be 1–{})", params.count_per_draw, MAX_COUNT)));
+    }
+
+    if params.draws == 0 || params.draws.saturating_mul(params.count_per_draw) > MAX_CHOOSE_MANY_PICKS {
+        return Err(ServiceError::Invalid(format!("Invalid draws: {} (draws × count_per_draw must be 1–{})", params.draws, MAX_CHOOSE_MANY_PICKS)));
+    }
+
+    check_item_count(params.items.len(), config)?;
+
+    let max_depth = config.max_json_depth.unwrap_or(DEFAULT_MAX_JSON_DEPTH);
+    if let Some(index) = params.items.iter().position(|item| json_depth(item) > max_depth) {
+        return Err(ServiceError::Invalid(format!("Item {} exceeds the maximum JSON depth of {}.", index, max_depth)));
+    }
+
+    let weights: Option<Vec<f64>> = params.weights_map.as_ref().map(|weights_map| {
+        params.items.iter().map(|item| weights_map.get(item_key(item).as_ref()).copied().unwrap_or(1.0)).collect()
+    });
+
+    let eligible = match &weights {
+        Some(weights) => validate_weights(weights)?,
+        None => params.items.len(),
+    };
+    if params.count_per_draw > eligible {
+        let what = if weights.is_some() { "number of items with positive weight" } else { "item count" };
+        return Err(ServiceError::Invalid(format!("count_per_draw must be <= {} ({}).", what, eligible)));
+    }
+
+    let mut rng = os_rng()?;
+    let draws = std::panic::catch_unwind(move || {
+        random_module::repeated_draws(&mut rng, params.items.len(), weights.as_deref(), params.draws, params.count_per_draw)
+    })
+    .map_err(|_| ServiceError::Internal("Panic occurred during repeated random choose"))?;
+
+    Ok(draws
+        .into_iter()
+        .map(|indices| indices.into_iter().map(|i| params.items[i].clone()).collect())
+        .collect())
+}
+
+
+#[post("/choose_histogram")]
+async fn choose_histogram_handler(req: HttpRequest, params: web::Json<HistogramParams>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "choose_histogram_handler", "Request from: {}", peer);
+
+    match run_histogram(&params) {
+        Ok(histogram) => success(histogram),
+        Err(err) => {
+            if let ServiceError::Invalid(msg) | ServiceError::TooLarge(msg) = &err {
+                warn!(target: "choose_histogram_handler", "{}", msg);
+            }
+            err.to_response()
+        }
+    }
+}
+
+
+// `trials` independent weighted draws, tallied per item key
+fn run_histogram(params: &HistogramParams) -> Result<serde_json::Map<String, Value>, ServiceError> {
+    if params.items.is_empty() {
+        return Err(ServiceError::Invalid("Items must not be empty.".to_string()));
+    }
+
+    if params.trials == 0 || params.trials > MAX_HISTOGRAM_TRIALS {
+        return Err(ServiceError::Invalid(format!("Invalid trials: {} (must be 1–{})", params.trials, MAX_HISTOGRAM_TRIALS)));
+    }
+
+    let weights: Vec<f64> = match &params.weights_map {
+        Some(weights_map) => params.items.iter().map(|item| weights_map.get(item_key(item).as_ref()).copied().unwrap_or(1.0)).collect(),
+        None => vec![1.0; params.items.len()],
+    };
+
+    if validate_weights(&weights)? == 0 {
+        return Err(ServiceError::Invalid("At least one item needs a positive weight.".to_string()));
+    }
+
+    let counts = random_module::weighted_histogram(&mut os_rng()?, &weights, params.trials);
+
+    let mut histogram = serde_json::Map::new();
+    for (item, count) in params.items.iter().zip(counts) {
+        let entry = histogram.entry(item_key(item).into_owned()).or_insert(Value::from(0u64));
+        *entry = Value::from(entry.as_u64().unwrap_or(0) + count as u64);
+    }
+
+    Ok(histogram)
+}
+
+
+// Splits `count` across the groups in proportion to their sizes (largest remainder), then samples each group
+fn choose_stratified(candidates: &[Value], key: &str, count: usize) -> Result<Generated<Vec<Value>>, ServiceError> {
+    let mut groups: Vec<(String, Vec<Value>)> = Vec::new();
+
+    for (index, item) in candidates.iter().enumerate() {
+        let Some(label) = item.get(key) else {
+            return Err(ServiceError::Invalid(format!("Item {} has no field {:?} (strata_key)", index, key)));
+        };
+        let label = item_key(label).into_owned();
+        match groups.iter_mut().find(|(existing, _)| *existing == label) {
+            Some((_, members)) => members.push(item.clone()),
+            None => groups.push((label, vec![item.clone()])),
+        }
+    }
+
+    let total = candidates.len();
+    let mut quotas: Vec<usize> = groups.iter().map(|(_, members)| count * members.len() / total).collect();
+
+    // Hand the seats lost to rounding down to the groups with the largest fractional parts, first-seen group on ties
+    let mut by_remainder: Vec<usize> = (0..groups.len()).collect();
+    by_remainder.sort_by_key(|&g| std::cmp::Reverse(count * groups[g].1.len() % total));
+    for &g in by_remainder.iter().take(count - quotas.iter().sum::<usize>()) {
+        quotas[g] += 1;
+    }
+
+    let entropy_bits = groups
+        .iter()
+        .zip(&quotas)
+        .map(|((_, members), &quota)| random_module::choose_entropy_bits(members.len(), quota))
+        .sum();
+
+    let mut rng = os_rng()?;
+    std::panic::catch_unwind(move || {
+        groups
+            .into_iter()
+            .zip(quotas)
+            .flat_map(|((_, members), quota)| random_module::generate_random_choose(&mut rng, members, quota))
+            .collect()
+    })
+    .map(|output| Generated { output, entropy_bits })
+    .map_err(|_| ServiceError::Internal("Panic occurred during stratified random choose"))
+}
+
+
+// Collapses items that are equal after trimming and lowercasing; the first occurrence is kept
+fn dedup_normalized(items: &[Value]) -> Vec<Value> {
+    let mut seen = std::collections::HashSet::new();
+    items
+        .iter()
+        .filter(|item| seen.insert(item_key(item).trim().to_lowercase()))
+        .cloned()
+        .collect()
+}
+
+
+// String items are keyed by their value, anything else by its JSON text
+fn item_key(item: &Value) -> std::borrow::Cow<'_, str> {
+    match item {
+        Value::String(s) => std::borrow::Cow::Borrowed(s),
+        other => std::borrow::Cow::Owned(other.to_string()),
+    }
+}
+
+
+// Scalars have depth 0; every enclosing array or object adds one level
+fn json_depth(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(json_depth).max().unwrap_or(0),
+        Value::Object(fields) => 1 + fields.values().map(json_depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(ui_handler)
+        .service(healthz_handler)
+        .service(ready_handler)
+        .service(status_handler)
+        .service(limits_handler)
+        .service(self_test_handler)
+        .service(config_handler)
+        .service(log_level_handler)
+        .service(stop_handler)
+        .service(cache_clear_handler)
+        .service(generate_handler)
+        .service(typed_generate_handler)
+        .service(choose_text_handler)
+        .service(choose_stream_handler)
+        .service(choose_handler)
+        .service(choose_many_handler)
+        .service(coords_handler)
+        .service(partial_permutation_handler)
+        .service(choose_histogram_handler)
+        .service(check_policy_handler)
+        .service(charset_info_handler)
+        .service(trace::trace_handler)
+        .service(metrics::metrics_handler)
+        .service(loot::loot_handler)
+        .service(cohort::cohort_handler)
+        .service(rpc::rpc_handler)
+        .service(ws::ws_generate_handler);
+}
+
+
+// Every route under `route_prefix`; with `probes_at_root` the probes answer at the root as well
+fn mount(cfg: &mut web::ServiceConfig, route_prefix: &str, probes_at_root: bool) {
+    if probes_at_root && !route_prefix.is_empty() {
+        cfg.service(healthz_handler).service(ready_handler);
+    }
+    cfg.service(web::scope(route_prefix).configure(routes));
+}
+
+
+thread_local! {
+    static WORKER_PINNED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+
+// Pins the calling worker thread to the next listed core, round-robin. The app factory runs once per listener
+// on every worker thread, so only the first call on a thread pins it; later ones leave it where it is
+fn pin_worker(affinity: &[core_affinity::CoreId], next_worker: &std::sync::atomic::AtomicUsize) {
+    if affinity.is_empty() || WORKER_PINNED.with(|pinned| pinned.replace(true)) {
+        return;
+    }
+
+    let worker = next_worker.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let core = affinity[worker % affinity.len()];
+    if core_affinity::set_for_current(core) {
+        info!(target: "main", "Worker {} pinned to core {}", worker, core.id);
+    } else {
+        warn!(target: "main", "Failed to pin worker {} to core {}", worker, core.id);
+    }
+}
+
+
+// On /stop: refuse new connections, let in-flight requests finish, and after shutdown_timeout drop the stragglers
+fn stop_on_request(server: actix_web::dev::ServerHandle, mut stop_rx: tokio::sync::mpsc::UnboundedReceiver<()>) {
+    tokio::spawn(async move {
+        if stop_rx.recv().await.is_some() {
+            server.stop(true).await;
+        }
+    });
+}
+
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let start = Instant::now();
+    let start_data = web::Data::new(start);
+    let config = load_config();
+    let config_data = web::Data::new(config.clone());
+    let in_flight = web::Data::new(InFlight::default());
+    let recent: web::Data<RecentValues> = web::Data::new(Mutex::new(LruCache::new(config.recent_window.unwrap_or(DEFAULT_RECENT_WINDOW))));
+    let (stop_tx, stop_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let stop_data: web::Data<StopSender> = web::Data::new(stop_tx);
+    let metrics = web::Data::new(Metrics::default());
+    let body_cache: web::Data<BodyCache> = web::Data::new(Mutex::new(LruCache::new(config.dedup_cache_size.unwrap_or(DEFAULT_DEDUP_CACHE_SIZE))));
+    let seeded_cache: web::Data<SeededCache> = web::Data::new(Mutex::new(LruCache::new(config.seeded_cache_size.unwrap_or(DEFAULT_SEEDED_CACHE_SIZE))));
+
+    let trace_buffer = web::Data::new(TraceBuffer::new(config.trace_lines.unwrap_or(DEFAULT_TRACE_LINES)));
+    let log_level_handle = web::Data::new(init_tracing(&config.logs_dir, &config.name_for_port_manager, config.log_level.as_deref(), config.log_to_syslog().unwrap_or(false), trace_buffer.clone().into_inner()));
+    install_panic_hook();
+
+    let seed_source = config.seed_source().unwrap_or(random_module::SeedSource::Os);
+    random_module::set_seed_source(seed_source);
+    if !seed_source.is_secure() {
+        warn!(target: "main", "seed_source is {:?}: random output is predictable, use for testing only", seed_source);
+    }
+    if let Some(path) = &config.entropy_file {
+        random_module::set_entropy_pool(random_module::read_entropy_file(path).unwrap_or_else(|e| {
+            error!(target: "main", "{}", e);
+            std::process::exit(1);
+        }));
+    }
+    if let Some(key) = config.signing_key.as_deref() {
+        signing::set_signing_key(signing::parse_signing_key(key).unwrap_or_else(|e| {
+            error!(target: "main", "{}", e);
+            std::process::exit(1);
+        }));
+    }
+    random_module::set_entropy_retries(config.entropy_retries.unwrap_or(random_module::DEFAULT_ENTROPY_RETRIES));
+    let _ = LEGACY_ERROR_FORMAT.set(config.legacy_error_format.unwrap_or(false));
+
+    // Explicit bind_addresses replace both the port manager lookup and the detected local address
+    let bind_addresses = config.bind_addresses().unwrap_or_default();
+    let listen_on: Vec<(String, u16)> = if bind_addresses.is_empty() {
+        let Some(port) = fetch_port(&config).await else {
+            error!(target: "main", "Failed to retrieve port. {} will not start.", &config.name_for_port_manager);
+            std::process::exit(1);
+        };
+
+        let ip = get_local_ip().map(|addr| addr.to_string()).unwrap_or("ERROR".to_string());
+
+        info!(target: "main", "Starting {} on {}:{}", &config.name_for_port_manager, ip, port);
+        vec![(ip, port)]
+    } else {
+        let listed: Vec<String> = bind_addresses.iter().map(ToString::to_string).collect();
+        info!(target: "main", "Starting {} on {}", &config.name_for_port_manager, listed.join(", "));
+        bind_addresses.iter().map(|address| (address.ip().to_string(), address.port())).collect()
+    };
+
+    let trusted_proxies = config.trusted_proxies().unwrap_or_default();
+
+    let affinity = config.affinity_cores().unwrap_or_else(|e| {
+        error!(target: "main", "{}", e);
+        std::process::exit(1);
+    });
+    let next_worker = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let route_prefix = config.route_prefix();
+    let probes_at_root = config.probes_at_root.unwrap_or(false);
+    let log_sample_rate = config.log_sample_rate;
+    let shutdown_timeout = config.shutdown_timeout_secs.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS);
+
+    let mut server = HttpServer::new(move || {
+        pin_worker(&affinity, &next_worker);
+
+        App::new()
+            .app_data(start_data.clone())
+            .app_data(config_data.clone())
+            .app_data(in_flight.clone())
+            .app_data(log_level_handle.clone())
+            .app_data(recent.clone())
+            .app_data(body_cache.clone())
+            .app_data(seeded_cache.clone())
+            .app_data(metrics.clone())
+            .app_data(stop_data.clone())
+            .app_data(trace_buffer.clone())
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .wrap(PayloadSizeGuard::new(config_data.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES)))
+            .wrap(InFlightLimit::new(in_flight.clone().into_inner(), config_data.max_concurrent_requests, config_data.max_concurrent_per_ip))
+            .wrap(LocalNetworkOnly::new(trusted_proxies.clone()))
+            .wrap_fn(move |req, srv| log_sampling::sample_request(srv.call(req), log_sample_rate))
+            .configure(|cfg| mount(cfg, &route_prefix, probes_at_root))
+    })
+    .workers(config.workers_count)
+    .shutdown_timeout(shutdown_timeout);
+
+    // Socket-level bound, enforced before any request is read
+    if let Some(max_connections) = config.max_connections_per_worker() {
+        server = server.max_connections(max_connections);
+    }
+
+    for (ip, port) in &listen_on {
+        server = server.bind((ip.as_str(), *port))?;
+    }
+    let server = server.run();
+
+    stop_on_request(server.handle(), stop_rx);
+
+    server.await
 }
\ No newline at end of file
@@ -0,0 +1,67 @@
+use actix_web::{get, web, Responder};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::success;
+use crate::utils::Config;
+
+
+
+#[derive(Default)]
+pub struct Metrics {
+    slow_requests: AtomicU64,
+}
+
+
+impl Metrics {
+    // Counts and logs the request when it took longer than `slow_request_threshold_ms`
+    pub fn observe(&self, endpoint: &str, duration: Duration, config: &Config) {
+        let Some(threshold_ms) = config.slow_request_threshold_ms else {
+            return;
+        };
+
+        let duration_ms = duration.as_millis() as u64;
+        if duration_ms > threshold_ms {
+            self.slow_requests.fetch_add(1, Ordering::Relaxed);
+            warn!(target: "slow_request", endpoint, duration_ms, threshold_ms, "Slow request");
+        }
+    }
+}
+
+
+
+#[get("/metrics")]
+pub async fn metrics_handler(metrics: web::Data<Metrics>, config: web::Data<Config>) -> impl Responder {
+    success(serde_json::json!({
+        "slow_requests": metrics.slow_requests.load(Ordering::Relaxed),
+        "slow_request_threshold_ms": config.slow_request_threshold_ms,
+    }))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use super::Metrics;
+    use crate::tests::config;
+
+    #[test]
+    fn only_requests_over_the_threshold_are_counted() {
+        let metrics = Metrics::default();
+        let threshold = config(json!({ "slow_request_threshold_ms": 50 }));
+
+        metrics.observe("/generate_random_string", Duration::from_millis(50), &threshold);
+        metrics.observe("/generate_random_string", Duration::from_millis(51), &threshold);
+        metrics.observe("/generate_random_choose", Duration::from_secs(2), &threshold);
+        assert_eq!(metrics.slow_requests.load(Ordering::Relaxed), 2);
+
+        // Without a threshold nothing is slow
+        metrics.observe("/generate_random_choose", Duration::from_secs(2), &config(json!({})));
+        assert_eq!(metrics.slow_requests.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,160 @@
+use actix_web::{dev::{ServiceRequest, ServiceResponse, Transform, Service}, http::header, HttpMessage, HttpResponse, Error, body::BoxBody};
+use futures::future::{ok, Ready, LocalBoxFuture};
+use std::task::{Context, Poll};
+use std::rc::Rc;
+use tracing::warn;
+
+use crate::failure;
+
+
+
+// Same as actix-web's default payload limit
+pub const DEFAULT_MAX_BODY_BYTES: usize = 256 * 1024;
+// NDJSON choose bodies are sampled as they stream in, so they may be far larger than a buffered body
+pub const DEFAULT_MAX_STREAM_BYTES: usize = 64 * 1024 * 1024;
+
+
+
+// Rejects oversized bodies from their headers alone, before any extractor reads the payload
+pub struct PayloadSizeGuard {
+    max_bytes: usize,
+}
+
+
+impl PayloadSizeGuard {
+    pub fn new(max_bytes: usize) -> Self {
+        Self { max_bytes }
+    }
+}
+
+
+impl<S> Transform<S, ServiceRequest> for PayloadSizeGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = PayloadSizeGuardMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(PayloadSizeGuardMiddleware {
+            service: Rc::new(service),
+            max_bytes: self.max_bytes,
+        })
+    }
+}
+
+
+pub struct PayloadSizeGuardMiddleware<S> {
+    service: Rc<S>,
+    max_bytes: usize,
+}
+
+
+impl<S> Service<ServiceRequest> for PayloadSizeGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = Rc::clone(&self.service);
+
+        if is_streamed(&req) {
+            return Box::pin(async move { svc.call(req).await });
+        }
+
+        let headers = req.headers();
+
+        let content_length = headers
+            .get(header::CONTENT_LENGTH)
+            .map(|value| value.to_str().ok().and_then(|s| s.trim().parse::<usize>().ok()));
+
+        let rejection = match content_length {
+            Some(Some(len)) if len > self.max_bytes => Some(
+                HttpResponse::PayloadTooLarge()
+                    .json(failure(format!("Request body exceeds {} bytes", self.max_bytes))),
+            ),
+            Some(Some(_)) => None,
+            Some(None) => Some(
+                HttpResponse::BadRequest()
+                    .json(failure("Invalid Content-Length header".to_string())),
+            ),
+            // A chunked body of unknown size could be arbitrarily large, so it must declare its length
+            None if headers.contains_key(header::TRANSFER_ENCODING) => Some(
+                HttpResponse::LengthRequired()
+                    .json(failure("Content-Length is required".to_string())),
+            ),
+            None => None,
+        };
+
+        if let Some(response) = rejection {
+            warn!(target: "payload_guard", "Rejecting {}: status {}", req.path(), response.status());
+            return Box::pin(async move { Ok(req.into_response(response)) });
+        }
+
+        Box::pin(async move { svc.call(req).await })
+    }
+}
+
+
+
+// The NDJSON form of /generate_random_choose enforces max_stream_bytes itself while reading, chunked bodies
+// included, so it is exempt here (also under route_prefix)
+fn is_streamed(req: &ServiceRequest) -> bool {
+    req.path().ends_with("/generate_random_choose")
+        && matches!(req.mime_type(), Ok(Some(mime)) if mime.essence_str() == "application/x-ndjson")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::{header, StatusCode}, test, web, App};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::PayloadSizeGuard;
+
+    #[actix_web::test]
+    async fn oversized_content_length_is_rejected_before_the_handler() {
+        let reached = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&reached);
+        let app = test::init_service(
+            App::new()
+                .wrap(PayloadSizeGuard::new(16))
+                .route("/echo", web::post().to(move |body: web::Bytes| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async move { body }
+                })),
+        )
+        .await;
+        let post = |content_length: &str| test::TestRequest::post().uri("/echo").insert_header((header::CONTENT_LENGTH, content_length));
+
+        // Only the header is sent: the guard answers without waiting for a body
+        let resp = test::call_service(&app, post("1048576").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, serde_json::json!({ "success": false, "data": "Request body exceeds 16 bytes" }));
+
+        let resp = test::call_service(&app, post("lots").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/echo").insert_header((header::TRANSFER_ENCODING, "chunked")).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::LENGTH_REQUIRED);
+        assert_eq!(reached.load(Ordering::SeqCst), 0);
+
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/echo").set_payload("sixteen bytes ok").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(reached.load(Ordering::SeqCst), 1);
+    }
+}
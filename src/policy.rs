@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use crate::random_module::CharClass;
+
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PasswordPolicy {
+    #[serde(default)]
+    pub min_length: usize,
+    #[serde(default)]
+    pub min_digits: usize,
+    #[serde(default)]
+    pub min_lowercase: usize,
+    #[serde(default)]
+    pub min_uppercase: usize,
+    #[serde(default)]
+    pub min_special: usize,
+}
+
+
+// Outcome of one policy field for a checked value
+#[derive(Serialize)]
+pub struct RuleCheck {
+    pub rule: &'static str,
+    pub required: usize,
+    pub actual: usize,
+    pub passed: bool,
+}
+
+
+
+impl PasswordPolicy {
+    pub fn required_classes(&self) -> Vec<CharClass> {
+        self.class_minimums()
+            .into_iter()
+            .filter(|(_, _, min)| *min > 0)
+            .map(|(class, _, _)| class)
+            .collect()
+    }
+
+    fn class_minimums(&self) -> [(CharClass, &'static str, usize); 4] {
+        [
+            (CharClass::Digits, "digit", self.min_digits),
+            (CharClass::Lowercase, "lowercase", self.min_lowercase),
+            (CharClass::Uppercase, "uppercase", self.min_uppercase),
+            (CharClass::Special, "special", self.min_special),
+        ]
+    }
+
+    // Rejects policies that no string from the given charset and length can ever satisfy
+    pub fn validate(&self, charset: &[u8], length: usize) -> Result<(), String> {
+        if self.min_length > length {
+            return Err(format!("min_length {} exceeds requested length {}", self.min_length, length));
+        }
+
+        for (class, name, min) in self.class_minimums() {
+            if min > 0 && !charset.iter().any(|&b| CharClass::of(b as char) == class) {
+                return Err(format!("policy requires {} {} characters but that charset is disabled", min, name));
+            }
+        }
+
+        let required: usize = self.class_minimums().iter().map(|(_, _, min)| min).sum();
+        if required > length {
+            return Err(format!("policy requires {} classed characters but length is {}", required, length));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_satisfied_by(&self, value: &str) -> bool {
+        self.check(value).iter().all(|check| check.passed)
+    }
+
+    // Every field of the policy, in declaration order, with what `value` actually has
+    pub fn check(&self, value: &str) -> Vec<RuleCheck> {
+        let count = |class: CharClass| value.chars().filter(|&c| CharClass::of(c) == class).count();
+        let rule = |rule, required, actual| RuleCheck { rule, required, actual, passed: actual >= required };
+
+        vec![
+            rule("min_length", self.min_length, value.chars().count()),
+            rule("min_digits", self.min_digits, count(CharClass::Digits)),
+            rule("min_lowercase", self.min_lowercase, count(CharClass::Lowercase)),
+            rule("min_uppercase", self.min_uppercase, count(CharClass::Uppercase)),
+            rule("min_special", self.min_special, count(CharClass::Special)),
+        ]
+    }
+}
@@ -1,6 +1,16 @@
-use rand::{rngs::OsRng, RngCore, SeedableRng, seq::index::sample, distributions::{Uniform, Distribution}};
+use rand::{rngs::OsRng, Rng, RngCore, SeedableRng, seq::index::sample, distributions::{Uniform, Distribution, WeightedIndex}};
 use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ServiceError;
 
 
 
@@ -8,21 +18,201 @@ const DIGITS: &[u8] = b"0123456789";
 const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
 const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const SPEC: &[u8] = b"!@#$%^&*-_=+~><?/";
+const HEX: &[u8] = b"0123456789abcdef";
+// Bitcoin alphabet: alphanumerics without the look-alikes 0, O, I and l
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+// QR code "alphanumeric" mode alphabet; anything outside it forces the less efficient byte mode
+const QR_ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+// Crockford's base32: digits and uppercase letters without I, L, O and U
+const CROCKFORD_BASE32: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+// Check symbols for the values 32–36 of the mod-37 checksum
+const CROCKFORD_CHECK_EXTRA: &[u8] = b"*~$=U";
+// Order in which enabled classes are concatenated. Seeded output indexes into the assembled charset,
+// so reordering these (or the bytes within a class) changes every seeded value
+const CHARSET_ORDER: [&[u8]; 4] = [DIGITS, LOWERCASE, UPPERCASE, SPEC];
+const CONSONANTS: &[u8] = b"bcdfghjklmnpqrstvwxyz";
+const VOWELS: &[u8] = b"aeiou";
+
+// Reported in response provenance; keep in sync with the generator SecureRandom wraps
+pub const ALGORITHM: &str = "ChaCha20";
+
+pub const PRESET_NAMES: &[&str] = &["alphanumeric", "hex", "base58", "printable", "qr_alphanumeric", "crockford_base32", "cyrillic", "greek", "hebrew"];
+
+
+
+// Where SecureRandom::new gets its seed. Only `Os` is secure; `Time` and `Fixed` exist for testing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedSource {
+    Os,
+    Time,
+    Fixed(u64),
+}
+
+
+impl SeedSource {
+    pub fn is_secure(&self) -> bool {
+        *self == SeedSource::Os
+    }
+}
+
+
+impl FromStr for SeedSource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "os" => Ok(SeedSource::Os),
+            "time" => Ok(SeedSource::Time),
+            _ => value
+                .strip_prefix("fixed:")
+                .and_then(|n| n.parse().ok())
+                .map(SeedSource::Fixed)
+                .ok_or_else(|| format!("{:?} is not a seed source (expected \"os\", \"time\" or \"fixed:<u64>\")", value)),
+        }
+    }
+}
+
+
+static SEED_SOURCE: OnceCell<SeedSource> = OnceCell::new();
+
+
+// Set once at startup; until then, and if never set, seeds come from the OS
+pub fn set_seed_source(source: SeedSource) {
+    let _ = SEED_SOURCE.set(source);
+}
+
+
+fn seed_source() -> SeedSource {
+    SEED_SOURCE.get().copied().unwrap_or(SeedSource::Os)
+}
+
+
+static ENTROPY_POOL: OnceCell<[u8; 32]> = OnceCell::new();
+
+
+// Extra entropy for OS-seeded RNGs, read from entropy_file once at startup
+pub fn set_entropy_pool(pool: [u8; 32]) {
+    let _ = ENTROPY_POOL.set(pool);
+}
+
+
+// The first 32 bytes of the file; works for regular files and for character devices that never end
+pub fn read_entropy_file(path: &str) -> Result<[u8; 32], String> {
+    let mut bytes = [0u8; 32];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .map_err(|e| format!("entropy_file {:?} must be readable and hold at least 32 bytes: {}", path, e))?;
+    Ok(bytes)
+}
+
+
+pub const DEFAULT_ENTROPY_RETRIES: u32 = 3;
+
+static ENTROPY_RETRIES: OnceCell<u32> = OnceCell::new();
 
 
+pub fn set_entropy_retries(retries: u32) {
+    let _ = ENTROPY_RETRIES.set(retries);
+}
+
+
+fn os_entropy(seed: &mut [u8]) -> Result<(), rand::Error> {
+    let retries = ENTROPY_RETRIES.get().copied().unwrap_or(DEFAULT_ENTROPY_RETRIES);
+    fill_with_retries(seed, retries, |seed| OsRng.try_fill_bytes(seed))
+}
+
+
+// Longest backoff between entropy retries, in yields of the worker thread
+const MAX_ENTROPY_BACKOFF_YIELDS: u32 = 64;
+
+
+// Retries transient failures of `fill` up to `retries` times. Between tries the thread yields 2, 4, 8, ...
+// (at most MAX_ENTROPY_BACKOFF_YIELDS) times: a short pause that lets a briefly unavailable entropy source
+// recover without putting a worker thread to sleep
+fn fill_with_retries<F>(seed: &mut [u8], retries: u32, mut fill: F) -> Result<(), rand::Error>
+where
+    F: FnMut(&mut [u8]) -> Result<(), rand::Error>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match fill(seed) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!(target: "random_module", "OS entropy read failed ({}), retry {} of {}", e, attempt, retries);
+                for _ in 0..2u32.saturating_pow(attempt).min(MAX_ENTROPY_BACKOFF_YIELDS) {
+                    std::thread::yield_now();
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+// Hashing keeps the seed at least as unpredictable as the stronger input, and a pool that never
+// changes still gives a different seed for every OS draw
+fn mix_seed(os_seed: &[u8; 32], pool: &[u8; 32]) -> [u8; 32] {
+    Sha256::new().chain_update(os_seed).chain_update(pool).finalize().into()
+}
 
-struct SecureRandom {
+
+fn time_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+}
+
+
+
+pub struct SecureRandom {
     rng: ChaCha20Rng,
 }
 
 
 impl SecureRandom {
-    fn new() -> Self {
-        let mut seed = [0u8; 32];
-        OsRng.fill_bytes(&mut seed);
-        Self {
-            rng: ChaCha20Rng::from_seed(seed),
-        }
+    // Panics when the OS has no entropy even after the retries; request paths use try_new to fail gracefully
+    pub fn new() -> Self {
+        Self::try_new().expect("OS entropy unavailable")
+    }
+
+    pub fn try_new() -> Result<Self, rand::Error> {
+        Self::from_source(seed_source())
+    }
+
+    fn from_source(source: SeedSource) -> Result<Self, rand::Error> {
+        Self::from_source_with_pool(source, ENTROPY_POOL.get())
+    }
+
+    fn from_source_with_pool(source: SeedSource, pool: Option<&[u8; 32]>) -> Result<Self, rand::Error> {
+        let rng = match source {
+            SeedSource::Os => {
+                let mut seed = [0u8; 32];
+                os_entropy(&mut seed)?;
+                ChaCha20Rng::from_seed(match pool {
+                    Some(pool) => mix_seed(&seed, pool),
+                    None => seed,
+                })
+            }
+            SeedSource::Time => ChaCha20Rng::seed_from_u64(time_seed()),
+            SeedSource::Fixed(seed) => ChaCha20Rng::seed_from_u64(seed),
+        };
+        Ok(Self { rng })
+    }
+
+    // Deterministic stream: the same (seed, stream) pair always yields the same output
+    pub fn from_seed_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        rng.set_stream(stream);
+        Self { rng }
+    }
+
+    // `true` with the given probability, clamped to 0.0–1.0
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.rng.gen_bool(probability.clamp(0.0, 1.0))
+    }
+
+    pub fn sample<T, D: Distribution<T>>(&mut self, dist: &D) -> T {
+        dist.sample(&mut self.rng)
     }
 
     fn random_index(&mut self, max: usize) -> usize {
@@ -33,6 +223,58 @@ impl SecureRandom {
     fn sample_indices(&mut self, len: usize, count: usize) -> Vec<usize> {
         sample(&mut self.rng, len, count).into_vec()
     }
+
+    // Weighted sampling without replacement: a drawn index gets weight 0 for the following draws
+    fn weighted_indices(&mut self, weights: &[f64], count: usize) -> Vec<usize> {
+        let dist = WeightedIndex::new(weights).expect("Weights must be finite, non-negative and not all zero");
+        self.weighted_indices_from(&dist, count)
+    }
+
+    // Same as weighted_indices for a prebuilt distribution, which is left untouched for reuse
+    fn weighted_indices_from(&mut self, dist: &WeightedIndex<f64>, count: usize) -> Vec<usize> {
+        if count == 1 {
+            return vec![dist.sample(&mut self.rng)];
+        }
+
+        let mut dist = dist.clone();
+        let mut picked = Vec::with_capacity(count);
+
+        for draw in 0..count {
+            let idx = dist.sample(&mut self.rng);
+            picked.push(idx);
+            if draw + 1 < count {
+                dist.update_weights(&[(idx, &0.0)]).expect("Not enough positive weights for the requested count");
+            }
+        }
+
+        picked
+    }
+}
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Digits,
+    Lowercase,
+    Uppercase,
+    Special,
+}
+
+
+impl CharClass {
+    pub fn of(c: char) -> Self {
+        if c.is_ascii_digit() {
+            CharClass::Digits
+        } else if c.is_ascii_lowercase() {
+            CharClass::Lowercase
+        } else if c.is_ascii_uppercase() {
+            CharClass::Uppercase
+        } else {
+            CharClass::Special
+        }
+    }
 }
 
 
@@ -46,19 +288,7 @@ struct RandomStringGenerator<'a> {
 
 impl<'a> RandomStringGenerator<'a> {
     fn new(use_digits: bool, use_lowercase: bool, use_uppercase: bool, use_spec: bool) -> Self {
-        let mut charset = Vec::new();
-        if use_digits {
-            charset.extend_from_slice(DIGITS);
-        }
-        if use_lowercase {
-            charset.extend_from_slice(LOWERCASE);
-        }
-        if use_uppercase {
-            charset.extend_from_slice(UPPERCASE);
-        }
-        if use_spec {
-            charset.extend_from_slice(SPEC);
-        }
+        let charset = build_charset(use_digits, use_lowercase, use_uppercase, use_spec);
 
         assert!(!charset.is_empty(), "Must be selected one or more types of symbols");
 
@@ -71,30 +301,38 @@ impl<'a> RandomStringGenerator<'a> {
 
 
     fn generate(&mut self, length: usize) -> String {
-        (0..length)
-            .map(|_| {
-                let idx = self.rng.random_index(self.charset.len());
-                self.charset[idx] as char
-            })
-            .collect()
+        sample_string(&mut self.rng, &self.charset, length)
     }
 }
 
 
 
-struct RandomSelector<T> {
-    rng: SecureRandom,
+fn sample_string(rng: &mut SecureRandom, charset: &[u8], length: usize) -> String {
+    assert!(!charset.is_empty(), "Must be selected one or more types of symbols");
+
+    (0..length)
+        .map(|_| {
+            let idx = rng.random_index(charset.len());
+            charset[idx] as char
+        })
+        .collect()
+}
+
+
+
+struct RandomSelector<'a, T> {
+    rng: &'a mut SecureRandom,
     _marker: std::marker::PhantomData<T>,
 }
 
 
-impl<T> RandomSelector<T>
+impl<'a, T> RandomSelector<'a, T>
 where
     T: Debug + Clone,
 {
-    fn new() -> Self {
+    fn new(rng: &'a mut SecureRandom) -> Self {
         Self {
-            rng: SecureRandom::new(),
+            rng,
             _marker: std::marker::PhantomData,
         }
     }
@@ -112,28 +350,515 @@ where
 
 
 
+// Algorithm R: keeps a uniform sample of `capacity` items from a stream of unknown length in one pass
+pub struct Reservoir<T> {
+    rng: SecureRandom,
+    capacity: usize,
+    seen: usize,
+    items: Vec<T>,
+}
+
+
+impl<T> Reservoir<T> {
+    pub fn new(rng: SecureRandom, capacity: usize) -> Self {
+        Self {
+            rng,
+            capacity,
+            seen: 0,
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn offer(&mut self, item: T) {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let slot = self.rng.random_index(self.seen + 1);
+            if slot < self.capacity {
+                self.items[slot] = item;
+            }
+        }
+        self.seen += 1;
+    }
+
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+
+
 // Main functions ==============================
+pub fn build_charset(use_digits: bool, use_lowercase: bool, use_uppercase: bool, use_spec: bool) -> Vec<u8> {
+    let enabled = [use_digits, use_lowercase, use_uppercase, use_spec];
+    CHARSET_ORDER.iter()
+        .zip(enabled)
+        .filter(|(_, on)| *on)
+        .flat_map(|(class, _)| class.iter().copied())
+        .collect()
+}
+
+
+// Named charsets usable instead of the four class flags
+pub fn preset_charset(name: &str) -> Option<Vec<u8>> {
+    match name {
+        "alphanumeric" => Some(build_charset(true, true, true, false)),
+        "hex" => Some(HEX.to_vec()),
+        "base58" => Some(BASE58.to_vec()),
+        // Every visible ASCII character, space excluded
+        "printable" => Some((b'!'..=b'~').collect()),
+        "qr_alphanumeric" => Some(QR_ALPHANUMERIC.to_vec()),
+        "crockford_base32" => Some(CROCKFORD_BASE32.to_vec()),
+        _ => None,
+    }
+}
+
+
+// Non-ASCII letter presets; these produce `char`s rather than bytes and go through generate_from_alphabet
+pub fn script_alphabet(name: &str) -> Option<Vec<char>> {
+    match name {
+        // Russian alphabet: А–я plus Ё/ё, all within U+0400–U+04FF
+        "cyrillic" => Some(('\u{0410}'..='\u{044F}').chain(['\u{0401}', '\u{0451}']).collect()),
+        // Α–Ω and α–ω; U+03A2 is unassigned, and final sigma ς is kept
+        "greek" => Some(('\u{0391}'..='\u{03A9}').chain('\u{03B1}'..='\u{03C9}').filter(|&c| c != '\u{03A2}').collect()),
+        // א–ת including the final forms
+        "hebrew" => Some(('\u{05D0}'..='\u{05EA}').collect()),
+        _ => None,
+    }
+}
+
+
+pub fn generate_from_alphabet(rng: &mut SecureRandom, alphabet: &[char], length: usize) -> String {
+    (0..length).map(|_| alphabet[rng.random_index(alphabet.len())]).collect()
+}
+
+
 pub fn generate_random_string(use_digits: bool, use_lowercase: bool, use_uppercase: bool, use_spec: bool, length: usize) -> String {
     RandomStringGenerator::new(use_digits, use_lowercase, use_uppercase, use_spec).generate(length)
 }
 
 
-pub fn generate_random_choose<T>(items: Vec<T>, count_of_items: usize) -> Vec<T>
+// `pattern` is a template of 'C' (consonant) and 'V' (vowel) slots, repeated until `length` is reached
+pub fn generate_from_syllables(rng: &mut SecureRandom, pattern: &str, length: usize) -> String {
+    pattern
+        .bytes()
+        .cycle()
+        .take(length)
+        .map(|slot| {
+            let pool = if slot == b'V' { VOWELS } else { CONSONANTS };
+            pool[rng.random_index(pool.len())] as char
+        })
+        .collect()
+}
+
+
+// `length - 1` random digits followed by their Luhn check digit, so the whole string passes the Luhn checksum
+pub fn generate_luhn(rng: &mut SecureRandom, length: usize) -> String {
+    let mut digits: Vec<u8> = (0..length.saturating_sub(1)).map(|_| DIGITS[rng.random_index(DIGITS.len())]).collect();
+    digits.push(luhn_check_digit(&digits));
+    String::from_utf8(digits).unwrap_or_default()
+}
+
+
+// Every second digit from the right of the payload is doubled (digits of the product summed)
+pub fn luhn_check_digit(payload: &[u8]) -> u8 {
+    let sum: u32 = payload
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &b)| {
+            let digit = (b - b'0') as u32;
+            if i % 2 == 0 { (digit * 2) / 10 + (digit * 2) % 10 } else { digit }
+        })
+        .sum();
+    b'0' + ((10 - sum % 10) % 10) as u8
+}
+
+
+// The encoded number modulo 37, reduced symbol by symbol so any length works; 32–36 map to `*~$=U`
+pub fn crockford_check_symbol(value: &str) -> char {
+    let remainder = value
+        .bytes()
+        .filter_map(|b| CROCKFORD_BASE32.iter().position(|&symbol| symbol == b.to_ascii_uppercase()))
+        .fold(0, |acc, digit| (acc * 32 + digit) % 37);
+
+    match CROCKFORD_BASE32.get(remainder) {
+        Some(&symbol) => symbol as char,
+        None => CROCKFORD_CHECK_EXTRA[remainder - 32] as char,
+    }
+}
+
+
+// A seed to export with a request; OS seeds go through entropy_retries like every other OS draw
+pub fn try_random_seed() -> Result<u64, ServiceError> {
+    match seed_source() {
+        SeedSource::Os => {
+            let mut seed = [0u8; 8];
+            os_entropy(&mut seed)?;
+            Ok(u64::from_le_bytes(seed))
+        }
+        SeedSource::Time => Ok(time_seed()),
+        SeedSource::Fixed(seed) => Ok(seed),
+    }
+}
+
+
+// `pools[i]` is the charset for position `i`, so the output has exactly `pools.len()` characters
+pub fn generate_positional(rng: &mut SecureRandom, pools: &[&[u8]]) -> String {
+    pools
+        .iter()
+        .map(|pool| {
+            assert!(!pool.is_empty(), "Every position needs at least one symbol");
+            pool[rng.random_index(pool.len())] as char
+        })
+        .collect()
+}
+
+
+pub fn generate_positional_matching<F>(rng: &mut SecureRandom, pools: &[&[u8]], accept: F, max_attempts: usize) -> Option<String>
+where
+    F: Fn(&str) -> bool,
+{
+    (0..max_attempts)
+        .map(|_| generate_positional(rng, pools))
+        .find(|candidate| accept(candidate))
+}
+
+
+// Keeps at most `max_classes` of the classes present in the charset; `required` classes are
+// always kept and the rest are picked at random
+pub fn limit_classes(rng: &mut SecureRandom, charset: &[u8], max_classes: usize, required: &[CharClass]) -> Vec<u8> {
+    let mut present: Vec<CharClass> = Vec::new();
+    for &b in charset {
+        let class = CharClass::of(b as char);
+        if !present.contains(&class) {
+            present.push(class);
+        }
+    }
+
+    if present.len() <= max_classes {
+        return charset.to_vec();
+    }
+
+    let mut keep: Vec<CharClass> = present.iter().copied().filter(|c| required.contains(c)).collect();
+    let optional: Vec<CharClass> = present.iter().copied().filter(|c| !required.contains(c)).collect();
+    let free_slots = max_classes.saturating_sub(keep.len()).min(optional.len());
+
+    keep.extend(rng.sample_indices(optional.len(), free_slots).into_iter().map(|i| optional[i]));
+
+    charset
+        .iter()
+        .copied()
+        .filter(|&b| keep.contains(&CharClass::of(b as char)))
+        .collect()
+}
+
+
+pub fn generate_random_choose<T>(rng: &mut SecureRandom, items: Vec<T>, count_of_items: usize) -> Vec<T>
 where
     T: Clone + std::fmt::Debug,
 {
-    let mut selector = RandomSelector::new();
+    let mut selector = RandomSelector::new(rng);
     selector.choose(&items, count_of_items)
 }
 
 
+pub fn generate_weighted_choose<T>(rng: &mut SecureRandom, items: &[T], weights: &[f64], count_of_items: usize) -> Vec<T>
+where
+    T: Clone,
+{
+    assert_eq!(items.len(), weights.len(), "Every item needs exactly one weight");
+
+    rng.weighted_indices(weights, count_of_items)
+        .into_iter()
+        .map(|i| items[i].clone())
+        .collect()
+}
+
+
+
+// The first `k` entries of a uniform random permutation of 0..n: Fisher–Yates stopped after k swaps, with only
+// the displaced slots kept in a map, so time and memory are O(k) however large `n` is. Requires k <= n
+pub fn partial_permutation(rng: &mut SecureRandom, n: u64, k: usize) -> Vec<u64> {
+    let mut displaced: HashMap<u64, u64> = HashMap::with_capacity(k);
+
+    (0..k as u64)
+        .map(|i| {
+            let j = rng.sample(&Uniform::new(i, n));
+            let picked = displaced.get(&j).copied().unwrap_or(j);
+            let current = displaced.remove(&i).unwrap_or(i);
+            // Slot i is never read again, so only slot j needs to remember what was swapped into it
+            if j != i {
+                displaced.insert(j, current);
+            }
+            picked
+        })
+        .collect()
+}
+
+
+
+// `count` (latitude, longitude) points in degrees inside the box. Uniform in the rectangle by default; `on_sphere`
+// draws the latitude through its sine instead, so points are spread evenly over the earth's surface and thin out
+// toward the poles. Callers validate the bounds
+pub fn generate_random_coords(rng: &mut SecureRandom, min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64, count: usize, on_sphere: bool) -> Vec<(f64, f64)> {
+    let lon = Uniform::new_inclusive(min_lon, max_lon);
+    let lat = if on_sphere {
+        Uniform::new_inclusive(min_lat.to_radians().sin(), max_lat.to_radians().sin())
+    } else {
+        Uniform::new_inclusive(min_lat, max_lat)
+    };
+
+    (0..count)
+        .map(|_| {
+            let drawn = rng.sample(&lat);
+            // asin of the clamped sine can land a hair outside the box through rounding, hence the final clamp
+            let latitude = if on_sphere { drawn.clamp(-1.0, 1.0).asin().to_degrees().clamp(min_lat, max_lat) } else { drawn };
+            (latitude, rng.sample(&lon))
+        })
+        .collect()
+}
+
+
+
+// `draws` independent selections of `count` distinct indices out of 0..len, sharing one RNG and, when
+// weighted, one precomputed distribution instead of setting both up per draw
+pub fn repeated_draws(rng: &mut SecureRandom, len: usize, weights: Option<&[f64]>, draws: usize, count: usize) -> Vec<Vec<usize>> {
+    match weights {
+        Some(weights) => {
+            let dist = WeightedIndex::new(weights).expect("Weights must be finite, non-negative and not all zero");
+            (0..draws).map(|_| rng.weighted_indices_from(&dist, count)).collect()
+        }
+        None => (0..draws).map(|_| rng.sample_indices(len, count)).collect(),
+    }
+}
+
+
+// Draws with replacement; `counts[i]` is how often item `i` came up in `trials` draws
+pub fn weighted_histogram(rng: &mut SecureRandom, weights: &[f64], trials: usize) -> Vec<usize> {
+    let dist = WeightedIndex::new(weights).expect("Weights must be finite, non-negative and not all zero");
+    let mut counts = vec![0; weights.len()];
+
+    for _ in 0..trials {
+        counts[rng.sample(&dist)] += 1;
+    }
+
+    counts
+}
+
+
+
+// `count` independent uniform draws from 0..len
+pub fn uniform_indices(rng: &mut SecureRandom, len: usize, count: usize) -> Vec<usize> {
+    (0..count).map(|_| rng.random_index(len)).collect()
+}
+
+
+
+// Fairness checks =============================
+// Pearson's chi-square statistic of `samples` (values in 0..buckets) against a uniform distribution.
+// Around `buckets - 1` for a fair source, much larger for a skewed one; out-of-range values are ignored
+pub fn distribution_uniformity(samples: &[usize], buckets: usize) -> f64 {
+    let mut observed = vec![0usize; buckets];
+    for &sample in samples.iter().filter(|&&s| s < buckets) {
+        observed[sample] += 1;
+    }
+
+    let total: usize = observed.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let expected = total as f64 / buckets as f64;
+    observed
+        .iter()
+        .map(|&count| {
+            let diff = count as f64 - expected;
+            diff * diff / expected
+        })
+        .sum()
+}
+
+
+// Approximate chi-square critical value for `df` degrees of freedom at significance 0.001 (Wilson–Hilferty)
+pub fn chi_square_critical(df: usize) -> f64 {
+    const Z_0_999: f64 = 3.090;
+    let df = df as f64;
+    let k = 2.0 / (9.0 * df);
+    df * (1.0 - k + Z_0_999 * k.sqrt()).powi(3)
+}
+
+
+
+// Entropy accounting ==========================
+pub fn positional_entropy_bits(pools: &[&[u8]]) -> f64 {
+    pools.iter().map(|pool| (pool.len() as f64).log2()).sum()
+}
+
+
+pub fn syllable_entropy_bits(pattern: &str, length: usize) -> f64 {
+    pattern
+        .bytes()
+        .cycle()
+        .take(length)
+        .map(|slot| if slot == b'V' { VOWELS.len() } else { CONSONANTS.len() })
+        .map(|pool| (pool as f64).log2())
+        .sum()
+}
+
+
+// log2 of the number of ordered selections of `count` out of `len` items
+pub fn choose_entropy_bits(len: usize, count: usize) -> f64 {
+    (0..count).map(|i| ((len - i) as f64).log2()).sum()
+}
+
+
 
 // test (DO NOT USE ON PROD)
+#[allow(dead_code)]
 fn main() {
     let random_str = generate_random_string(true, true, true, true, 16);
     println!("[TEST] generate random string: {}", random_str);
 
     let items = vec![1, 2, 3];
-    let random_select = generate_random_choose(items, 2);
+    let random_select = generate_random_choose(&mut SecureRandom::new(), items, 2);
     println!("[TEST] generate random choose: {:?}", random_select);
 }
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(source: SeedSource) -> Vec<u64> {
+        let mut rng = SecureRandom::from_source(source).unwrap();
+        (0..8).map(|_| rng.sample(&Uniform::new_inclusive(0, u64::MAX))).collect()
+    }
+
+    #[test]
+    fn fixed_seed_source_is_reproducible_and_os_is_not() {
+        assert_eq!(draw(SeedSource::Fixed(407)), draw(SeedSource::Fixed(407)));
+        assert_ne!(draw(SeedSource::Fixed(407)), draw(SeedSource::Fixed(408)));
+        assert_ne!(draw(SeedSource::Os), draw(SeedSource::Os));
+    }
+
+    #[test]
+    fn entropy_file_is_mixed_into_os_seeds() {
+        let path = std::env::temp_dir().join(format!("entropy_file_test_{}", std::process::id()));
+        std::fs::write(&path, [0x5a; 40]).unwrap();
+        let pool = read_entropy_file(path.to_str().unwrap()).unwrap();
+        std::fs::write(&path, [0x5a; 16]).unwrap();
+        let short = read_entropy_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(pool, [0x5a; 32]);
+        assert!(short.unwrap_err().contains("at least 32 bytes"));
+
+        // The same OS draw gives a different seed with the pool, and the mix depends on both inputs
+        let os_seed = [7u8; 32];
+        assert_ne!(mix_seed(&os_seed, &pool), os_seed);
+        assert_ne!(mix_seed(&os_seed, &pool), mix_seed(&[8u8; 32], &pool));
+        assert_ne!(mix_seed(&os_seed, &pool), mix_seed(&os_seed, &[0x5b; 32]));
+
+        let mut first = SecureRandom::from_source_with_pool(SeedSource::Os, Some(&pool)).unwrap();
+        let mut second = SecureRandom::from_source_with_pool(SeedSource::Os, Some(&pool)).unwrap();
+        let dist = Uniform::new_inclusive(0, u64::MAX);
+        assert_ne!(first.sample(&dist), second.sample(&dist), "a fixed pool must not fix the seed");
+    }
+
+    #[test]
+    fn transient_entropy_failures_are_retried() {
+        // Fails twice, then fills the seed with 7s
+        let flaky = || {
+            let mut calls = 0;
+            move |seed: &mut [u8]| {
+                calls += 1;
+                if calls <= 2 {
+                    return Err(rand::Error::new(std::io::Error::other("entropy busy")));
+                }
+                seed.fill(7);
+                Ok(())
+            }
+        };
+
+        let mut seed = [0u8; 32];
+        assert!(fill_with_retries(&mut seed, 2, flaky()).is_ok());
+        assert_eq!(seed, [7; 32]);
+
+        let mut seed = [0u8; 32];
+        let err = fill_with_retries(&mut seed, 1, flaky()).unwrap_err();
+        assert_eq!(err.to_string(), "entropy busy");
+        assert_eq!(seed, [0; 32]);
+    }
+
+    #[test]
+    fn retry_budget_bounds_the_entropy_reads() {
+        // A backend that fails `failures` times; returns the result and how often it was called
+        let run = |failures: usize, retries: u32| {
+            let mut calls = 0;
+            let result = fill_with_retries(&mut [0u8; 8], retries, |seed| {
+                calls += 1;
+                if calls <= failures {
+                    return Err(rand::Error::new(std::io::Error::other("entropy busy")));
+                }
+                seed.fill(1);
+                Ok(())
+            });
+            (result.is_ok(), calls)
+        };
+
+        assert_eq!(run(0, 3), (true, 1));
+        assert_eq!(run(3, 3), (true, 4), "N failures fit in N retries");
+        assert_eq!(run(4, 3), (false, 4), "no read past the budget");
+        assert_eq!(run(usize::MAX, 0), (false, 1));
+        // Backoff is capped, so even a large budget gives up promptly
+        let started = std::time::Instant::now();
+        assert_eq!(run(usize::MAX, 100), (false, 101));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn charset_assembly_order_is_pinned() {
+        assert_eq!(build_charset(true, false, false, true), b"0123456789!@#$%^&*-_=+~><?/");
+        assert_eq!(build_charset(false, true, true, false), [LOWERCASE, UPPERCASE].concat());
+        // The flags never reorder the classes, whichever of them are set
+        assert_eq!(build_charset(true, true, true, true), [DIGITS, LOWERCASE, UPPERCASE, SPEC].concat());
+    }
+
+    #[test]
+    fn chi_square_tells_fair_from_skewed() {
+        let fair: Vec<usize> = (0..10_000).map(|i| i % 10).collect();
+        assert_eq!(distribution_uniformity(&fair, 10), 0.0);
+
+        let mut rng = SecureRandom::from_seed_stream(414, 0);
+        let random: Vec<usize> = (0..10_000).map(|_| rng.random_index(10)).collect();
+        assert!(distribution_uniformity(&random, 10) < chi_square_critical(9));
+
+        // Every other sample lands in bucket 0
+        let skewed: Vec<usize> = (0..10_000).map(|i| if i % 2 == 0 { 0 } else { i % 10 }).collect();
+        assert!(distribution_uniformity(&skewed, 10) > chi_square_critical(9));
+    }
+
+    #[test]
+    fn chi_square_ignores_out_of_range_samples() {
+        assert_eq!(distribution_uniformity(&[0, 1, 2, 3, 7, 9], 4), 0.0);
+        assert_eq!(distribution_uniformity(&[5, 6], 4), 0.0);
+        // Wilson–Hilferty against the tabulated 27.88 for df = 9
+        assert!((chi_square_critical(9) - 27.88).abs() < 0.2);
+    }
+
+    #[test]
+    fn seed_source_parses_from_config_strings() {
+        assert_eq!("os".parse(), Ok(SeedSource::Os));
+        assert_eq!("time".parse(), Ok(SeedSource::Time));
+        assert_eq!("fixed:42".parse(), Ok(SeedSource::Fixed(42)));
+        assert!("fixed:".parse::<SeedSource>().is_err());
+        assert!("urandom".parse::<SeedSource>().is_err());
+        assert!(SeedSource::Os.is_secure() && !SeedSource::Time.is_secure() && !SeedSource::Fixed(1).is_secure());
+    }
+}
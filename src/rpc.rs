@@ -0,0 +1,139 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{info, warn, error};
+
+use crate::{run_choose, CHAOS_MARKER, run_generate, ChooseParams, GenerateParams, RecentValues, ServiceError};
+use crate::utils::Config;
+
+
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+
+impl RpcResponse {
+    fn success(id: Value, result: Value) -> Self {
+        Self { jsonrpc: "2.0", result: Some(result), error: None, id }
+    }
+
+    fn failure(id: Value, code: i64, message: String) -> Self {
+        Self { jsonrpc: "2.0", result: None, error: Some(RpcError { code, message }), id }
+    }
+}
+
+
+
+fn call_method(method: &str, params: Value, config: &Config, recent: &RecentValues) -> Result<Value, RpcError> {
+    let outcome = match method {
+        "generate_random_string" => {
+            let params: GenerateParams = serde_json::from_value(params)
+                .map_err(|e| RpcError { code: INVALID_PARAMS, message: format!("Invalid params: {}", e) })?;
+            run_generate(&params, config, recent).map(|generated| serde_json::to_value(generated.output).unwrap_or(Value::Null))
+        }
+        "generate_random_choose" => {
+            let params: ChooseParams<Value> = serde_json::from_value(params)
+                .map_err(|e| RpcError { code: INVALID_PARAMS, message: format!("Invalid params: {}", e) })?;
+            run_choose(&params, config).map(|generated| generated.output)
+        }
+        _ => {
+            return Err(RpcError { code: METHOD_NOT_FOUND, message: format!("Method not found: {}", method) });
+        }
+    };
+
+    outcome.map_err(|err| match err {
+        ServiceError::Invalid(msg) | ServiceError::TooLarge(msg) => RpcError { code: INVALID_PARAMS, message: msg },
+        ServiceError::Internal(msg) => {
+            error!(target: "rpc_handler", "{}", msg);
+            RpcError { code: INTERNAL_ERROR, message: "Internal error".to_string() }
+        }
+        ServiceError::Injected => RpcError { code: INTERNAL_ERROR, message: CHAOS_MARKER.to_string() },
+    })
+}
+
+
+// Returns None for notifications (requests without an "id" member)
+fn handle_call(call: Value, config: &Config, recent: &RecentValues) -> Option<RpcResponse> {
+    let Value::Object(mut call) = call else {
+        return Some(RpcResponse::failure(Value::Null, INVALID_REQUEST, "Invalid Request".to_string()));
+    };
+
+    let id = call.remove("id");
+    let is_valid = call.get("jsonrpc").and_then(Value::as_str) == Some("2.0")
+        && call.get("method").is_some_and(Value::is_string);
+
+    if !is_valid {
+        return Some(RpcResponse::failure(id.unwrap_or(Value::Null), INVALID_REQUEST, "Invalid Request".to_string()));
+    }
+
+    let method = call.get("method").and_then(Value::as_str).unwrap_or_default().to_string();
+    let params = call.remove("params").unwrap_or(Value::Null);
+    let outcome = call_method(&method, params, config, recent);
+
+    if let Err(err) = &outcome {
+        warn!(target: "rpc_handler", "Method {} failed: {}", method, err.message);
+    }
+
+    let id = id?;
+    Some(match outcome {
+        Ok(result) => RpcResponse::success(id, result),
+        Err(err) => RpcResponse::failure(id, err.code, err.message),
+    })
+}
+
+
+
+#[post("/rpc")]
+pub async fn rpc_handler(req: HttpRequest, body: web::Bytes, config: web::Data<Config>, recent: web::Data<RecentValues>) -> impl Responder {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "rpc_handler", "Request from: {}", peer);
+
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!(target: "rpc_handler", "Parse error: {}", e);
+            return HttpResponse::Ok().json(RpcResponse::failure(Value::Null, PARSE_ERROR, "Parse error".to_string()));
+        }
+    };
+
+    match payload {
+        Value::Array(calls) if calls.is_empty() => {
+            HttpResponse::Ok().json(RpcResponse::failure(Value::Null, INVALID_REQUEST, "Invalid Request".to_string()))
+        }
+        Value::Array(calls) => {
+            let responses: Vec<RpcResponse> = calls.into_iter().filter_map(|call| handle_call(call, &config, &recent)).collect();
+            if responses.is_empty() {
+                HttpResponse::NoContent().finish()
+            } else {
+                HttpResponse::Ok().json(responses)
+            }
+        }
+        call => match handle_call(call, &config, &recent) {
+            Some(response) => HttpResponse::Ok().json(response),
+            None => HttpResponse::NoContent().finish(),
+        },
+    }
+}
@@ -0,0 +1,103 @@
+use actix_web::{http::header, HttpResponse, HttpResponseBuilder};
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+
+
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const PUBLIC_KEY_HEADER: &str = "X-Signature-Public-Key";
+
+
+
+static SIGNING_KEY: OnceCell<SigningKey> = OnceCell::new();
+
+
+// `signing_key` holds the 32-byte Ed25519 secret key (seed), base64 encoded
+pub fn parse_signing_key(encoded: &str) -> Result<SigningKey, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| format!("signing_key: not valid base64 ({})", e))?;
+    let seed: [u8; 32] = bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("signing_key: expected 32 bytes, got {}", bytes.len()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+
+// Set once at startup; without a key responses go out unsigned
+pub fn set_signing_key(key: SigningKey) {
+    let _ = SIGNING_KEY.set(key);
+}
+
+
+// Serializes `body` once and, with a signing key configured, signs exactly those bytes, so clients can verify
+// the body as received against the signature and public key headers
+pub fn signed_json<T: Serialize>(builder: HttpResponseBuilder, body: &T) -> HttpResponse {
+    signed_with(SIGNING_KEY.get(), builder, body)
+}
+
+
+fn signed_with<T: Serialize>(key: Option<&SigningKey>, mut builder: HttpResponseBuilder, body: &T) -> HttpResponse {
+    let Some(bytes) = crate::serialize_body(body) else {
+        return crate::unserializable();
+    };
+
+    if let Some(key) = key {
+        let engine = base64::engine::general_purpose::STANDARD;
+        builder
+            .insert_header((SIGNATURE_HEADER, engine.encode(key.sign(&bytes).to_bytes())))
+            .insert_header((PUBLIC_KEY_HEADER, engine.encode(key.verifying_key().to_bytes())));
+    }
+
+    builder.insert_header(header::ContentType::json()).body(bytes)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+    use serde_json::json;
+
+    fn header(resp: &HttpResponse, name: &str) -> Option<Vec<u8>> {
+        let value = resp.headers().get(name)?.to_str().unwrap();
+        Some(base64::engine::general_purpose::STANDARD.decode(value).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn signature_verifies_against_the_exact_body() {
+        let key = parse_signing_key(&base64::engine::general_purpose::STANDARD.encode([7u8; 32])).unwrap();
+        let resp = signed_with(Some(&key), HttpResponse::Ok(), &json!({ "success": true, "data": "Kx7-pQ" }));
+
+        let public_key: [u8; 32] = header(&resp, PUBLIC_KEY_HEADER).unwrap().try_into().unwrap();
+        let signature: [u8; 64] = header(&resp, SIGNATURE_HEADER).unwrap().try_into().unwrap();
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+
+        let public_key = VerifyingKey::from_bytes(&public_key).unwrap();
+        let signature = Signature::from_bytes(&signature);
+        assert_eq!(public_key, key.verifying_key());
+        assert!(public_key.verify(&body, &signature).is_ok());
+
+        // One changed byte, or the same data serialized differently, no longer verifies
+        let mut tampered = body.to_vec();
+        tampered[body.len() - 3] ^= 1;
+        assert!(public_key.verify(&tampered, &signature).is_err());
+        assert!(public_key.verify(br#"{ "success": true, "data": "Kx7-pQ" }"#, &signature).is_err());
+    }
+
+    #[actix_web::test]
+    async fn without_a_key_the_body_is_unsigned() {
+        let resp = signed_with(None, HttpResponse::Ok(), &json!({ "success": true, "data": 1 }));
+        assert!(header(&resp, SIGNATURE_HEADER).is_none() && header(&resp, PUBLIC_KEY_HEADER).is_none());
+        assert_eq!(actix_web::body::to_bytes(resp.into_body()).await.unwrap(), r#"{"data":1,"success":true}"#);
+    }
+
+    #[test]
+    fn signing_key_must_be_32_base64_bytes() {
+        assert_eq!(parse_signing_key("c2VjcmV0").unwrap_err(), "signing_key: expected 32 bytes, got 6");
+        assert!(parse_signing_key("not base64!").unwrap_err().starts_with("signing_key: not valid base64"));
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const FACILITY_DAEMON: u8 = 3;
+
+
+
+// Sends each formatted event as one RFC 3164 datagram to the local syslog daemon
+pub struct SyslogMakeWriter {
+    socket: UnixDatagram,
+    tag: String,
+}
+
+
+impl SyslogMakeWriter {
+    pub fn connect(name: &str) -> io::Result<Self> {
+        Self::connect_to(SYSLOG_SOCKET, name)
+    }
+
+    fn connect_to(path: impl AsRef<Path>, name: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self { socket, tag: format!("{}[{}]", name, std::process::id()) })
+    }
+
+    fn writer(&self, severity: u8) -> SyslogWriter<'_> {
+        SyslogWriter { socket: &self.socket, tag: &self.tag, severity, bytes: Vec::new() }
+    }
+}
+
+
+impl<'a> MakeWriter<'a> for SyslogMakeWriter {
+    type Writer = SyslogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(6)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let severity = match *meta.level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            _ => 7,
+        };
+        self.writer(severity)
+    }
+}
+
+
+// One writer per event; the finished line is sent when the writer is dropped
+pub struct SyslogWriter<'a> {
+    socket: &'a UnixDatagram,
+    tag: &'a str,
+    severity: u8,
+    bytes: Vec<u8>,
+}
+
+
+impl io::Write for SyslogWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+impl Drop for SyslogWriter<'_> {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.bytes);
+        let message = format!("<{}>{}: {}", FACILITY_DAEMON * 8 + self.severity, self.tag, text.trim_end());
+        // A syslog daemon that went away must not break the request that logged
+        let _ = self.socket.send(message.as_bytes());
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn each_event_is_one_tagged_datagram() {
+        let path = std::env::temp_dir().join(format!("syslog_test_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let daemon = UnixDatagram::bind(&path).unwrap();
+
+        let syslog = SyslogMakeWriter::connect_to(&path, "random_module").unwrap();
+        {
+            let mut writer = syslog.make_writer();
+            write!(writer, "INFO main: started ").unwrap();
+            writeln!(writer, "on port 8080").unwrap();
+        }
+
+        let mut buf = [0u8; 256];
+        let len = daemon.recv(&mut buf).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // daemon (3) * 8 + info (6)
+        assert_eq!(std::str::from_utf8(&buf[..len]).unwrap(), format!("<30>random_module[{}]: INFO main: started on port 8080", std::process::id()));
+
+        // Nothing listening: the caller falls back to the log file
+        assert!(SyslogMakeWriter::connect_to(&path, "random_module").is_err());
+    }
+}
@@ -0,0 +1,651 @@
+use actix_web::{http::{header, StatusCode}, test};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+use super::{app, assert_share, config, data, post, send, tally, Harness};
+use crate::payload::{PayloadSizeGuard, DEFAULT_MAX_BODY_BYTES};
+
+
+
+#[actix_web::test]
+async fn normalized_duplicates_are_one_candidate() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items = json!(["John", "john ", " JOHN", "Mary"]);
+
+    for _ in 0..30 {
+        let body = json!({ "items": items, "count": 2, "dedup_normalized": true });
+        let picks = data(&app, post("/generate_random_choose", body).to_request()).await;
+        let picks: Vec<&str> = picks.as_array().unwrap().iter().map(|pick| pick.as_str().unwrap()).collect();
+
+        // Two distinct candidates, so both are always picked and John only in its first spelling
+        assert_eq!(picks.len(), 2);
+        assert!(picks.contains(&"John") && picks.contains(&"Mary"), "{:?}", picks);
+    }
+
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": items, "count": 3, "dedup_normalized": true })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Count must be <= distinct item count.");
+
+    let picks = data(&app, post("/generate_random_choose", json!({ "items": items, "count": 3 })).to_request()).await;
+    assert_eq!(picks.as_array().map(Vec::len), Some(3), "without the option every spelling is a candidate");
+}
+
+
+
+#[actix_web::test]
+async fn weights_map_sets_selection_frequency() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let trials = 3000;
+
+    // "c" is not listed, so it weighs 1.0
+    let body = json!({ "items": ["a", "b", "c"], "count": 1, "weights_map": { "a": 6.0, "b": 3.0 } });
+    let counts = tally(&app, || post("/generate_random_choose", body.clone()).to_request(), trials).await;
+
+    for (item, expected) in [("a", 0.6), ("b", 0.3), ("c", 0.1)] {
+        assert_share(item, counts.get(item).copied().unwrap_or(0), trials, expected, 0.04);
+    }
+
+    let body = json!({ "items": ["a", "b"], "count": 1, "weights_map": { "a": 0.0, "b": 1.0 } });
+    let counts = tally(&app, || post("/generate_random_choose", body.clone()).to_request(), 200).await;
+    assert_eq!(counts.get("a"), None, "a zero weight is never picked");
+}
+
+
+#[actix_web::test]
+async fn items_deeper_than_max_json_depth_are_rejected() {
+    let harness = Harness::new(config(json!({ "max_json_depth": 3 })));
+    let app = app!(harness);
+
+    // Depth 3: an object holding an array holding an object
+    let shallow = json!({ "tags": [{ "name": "a" }] });
+    let picks = data(&app, post("/generate_random_choose", json!({ "items": [1, shallow], "count": 2 })).to_request()).await;
+    assert_eq!(picks.as_array().map(Vec::len), Some(2));
+
+    let deep = json!({ "tags": [{ "name": ["a"] }] });
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": [1, deep], "count": 1 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Item 1 exceeds the maximum JSON depth of 3.");
+}
+
+
+#[actix_web::test]
+async fn weight_by_length_favours_longer_strings() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let trials = 3000;
+
+    let body = json!({ "items": ["a", "bbb", "cccccc"], "count": 1, "weight_by_length": true });
+    let counts = tally(&app, || post("/generate_random_choose", body.clone()).to_request(), trials).await;
+
+    for (item, expected) in [("a", 0.1), ("bbb", 0.3), ("cccccc", 0.6)] {
+        assert_share(item, counts.get(item).copied().unwrap_or(0), trials, expected, 0.04);
+    }
+}
+
+
+#[actix_web::test]
+async fn histogram_approximates_the_weights() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let trials = 20_000;
+
+    let body = json!({ "items": ["a", "b", "c", "d"], "weights_map": { "a": 5.0, "b": 3.0, "d": 0.0 }, "trials": trials });
+    let histogram = data(&app, post("/choose_histogram", body).to_request()).await;
+
+    assert_eq!(histogram.as_object().unwrap().values().map(|count| count.as_u64().unwrap()).sum::<u64>(), trials as u64);
+    assert_eq!(histogram["d"], 0, "zero-weight items are listed with no draws");
+    // "c" is unlisted, so it weighs 1.0 out of 9.0
+    for (item, expected) in [("a", 5.0 / 9.0), ("b", 3.0 / 9.0), ("c", 1.0 / 9.0)] {
+        assert_share(item, histogram[item].as_u64().unwrap() as usize, trials, expected, 0.02);
+    }
+}
+
+
+#[actix_web::test]
+async fn plain_text_body_is_one_item_per_line() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let lines = ["alpha", "beta", "gamma delta", "epsilon"];
+    let body = "alpha\r\nbeta\n\ngamma delta\nepsilon\n";
+
+    for _ in 0..20 {
+        let req = test::TestRequest::post()
+            .uri("/generate_random_choose?count=3")
+            .insert_header((header::CONTENT_TYPE, "text/plain"))
+            .set_payload(body)
+            .to_request();
+        let picks = data(&app, req).await;
+        let picks: Vec<&str> = picks.as_array().unwrap().iter().map(|pick| pick.as_str().unwrap()).collect();
+
+        assert_eq!(picks.len(), 3);
+        assert!(picks.iter().all(|pick| lines.contains(pick)), "{:?}", picks);
+    }
+
+    // Blank lines are not items, so only four candidates exist
+    let req = test::TestRequest::post()
+        .uri("/generate_random_choose?count=5")
+        .insert_header((header::CONTENT_TYPE, "text/plain"))
+        .set_payload(body)
+        .to_request();
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn ndjson_bodies_get_their_own_limit_and_may_be_chunked() {
+    let ndjson: String = (0..10_000).map(|id| format!("{{\"id\": {}, \"name\": \"item-{:05}-padding-padding\"}}\n", id, id)).collect();
+    assert!(ndjson.len() > DEFAULT_MAX_BODY_BYTES);
+    let stream = |length: Option<usize>| {
+        let req = test::TestRequest::post()
+            .uri("/generate_random_choose?count=3")
+            .insert_header((header::CONTENT_TYPE, "application/x-ndjson"))
+            .set_payload(ndjson.clone());
+        match length {
+            Some(length) => req.insert_header((header::CONTENT_LENGTH, length)),
+            None => req.insert_header((header::TRANSFER_ENCODING, "chunked")),
+        }
+        .to_request()
+    };
+
+    let harness = Harness::new(config(json!({})));
+    let app = test::init_service(
+        actix_web::App::new()
+            .configure(|cfg| harness.register(cfg))
+            .wrap(PayloadSizeGuard::new(DEFAULT_MAX_BODY_BYTES))
+            .configure(crate::routes),
+    )
+    .await;
+
+    for length in [Some(ndjson.len()), None] {
+        let picks = data(&app, stream(length)).await;
+        let picks = picks.as_array().unwrap();
+        assert_eq!(picks.len(), 3);
+        assert!(picks.iter().all(|pick| pick["id"].as_u64().is_some_and(|id| id < 10_000)), "{:?}", picks);
+    }
+
+    // Other bodies on the same path are still held to max_body_bytes
+    let items: Vec<u32> = (0..60_000).collect();
+    let body = serde_json::to_vec(&json!({ "items": items, "count": 1 })).unwrap();
+    let req = test::TestRequest::post().uri("/generate_random_choose").insert_header((header::CONTENT_TYPE, "application/json")).insert_header((header::CONTENT_LENGTH, body.len())).set_payload(body).to_request();
+    assert_eq!(send(&app, req).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let harness = Harness::new(config(json!({ "max_stream_bytes": 100_000 })));
+    let app = test::init_service(
+        actix_web::App::new()
+            .configure(|cfg| harness.register(cfg))
+            .wrap(PayloadSizeGuard::new(DEFAULT_MAX_BODY_BYTES))
+            .configure(crate::routes),
+    )
+    .await;
+
+    for length in [Some(ndjson.len()), None] {
+        let (status, body) = send(&app, stream(length)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["data"], "Request body exceeds 100000 bytes");
+    }
+}
+
+
+#[actix_web::test]
+async fn strata_keep_the_group_proportions() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    // 60% eu, 30% us, 10% apac
+    let items: Vec<_> = (0..100)
+        .map(|id| {
+            let region = match id % 10 { 0..=5 => "eu", 6..=8 => "us", _ => "apac" };
+            json!({ "id": id, "region": region })
+        })
+        .collect();
+
+    for (count, expected) in [(10, [6, 3, 1]), (20, [12, 6, 2]), (7, [4, 2, 1])] {
+        let picks = data(&app, post("/generate_random_choose", json!({ "items": items, "count": count, "strata_key": "region" })).to_request()).await;
+        let picks = picks.as_array().unwrap();
+        let in_region = |region: &str| picks.iter().filter(|pick| pick["region"] == region).count();
+
+        assert_eq!(picks.len(), count);
+        assert_eq!([in_region("eu"), in_region("us"), in_region("apac")], expected, "count {}", count);
+        let ids: HashSet<u64> = picks.iter().map(|pick| pick["id"].as_u64().unwrap()).collect();
+        assert_eq!(ids.len(), count, "no item is picked twice");
+    }
+
+    let mut unlabeled = items.clone();
+    unlabeled[3] = json!({ "id": 3 });
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": unlabeled, "count": 5, "strata_key": "region" })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Item 3 has no field \"region\" (strata_key)");
+}
+
+
+#[actix_web::test]
+async fn distinct_output_collapses_repeated_values() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items = json!(["a", "a", "a", "b", "b", "c"]);
+    let mut shorter = false;
+
+    for _ in 0..50 {
+        let picks = data(&app, post("/generate_random_choose", json!({ "items": items, "count": 4, "distinct_output": true })).to_request()).await;
+        let picks: Vec<&str> = picks.as_array().unwrap().iter().map(|pick| pick.as_str().unwrap()).collect();
+
+        let unique: HashSet<&str> = picks.iter().copied().collect();
+        assert_eq!(unique.len(), picks.len(), "{:?} repeats a value", picks);
+        assert!((2..=3).contains(&picks.len()), "{:?}", picks);
+        shorter |= picks.len() < 3;
+    }
+    // Four picks out of three distinct values always collapse, sometimes down to two
+    assert!(shorter);
+
+    // Without the flag the drawn copies all come back
+    let picks = data(&app, post("/generate_random_choose", json!({ "items": items, "count": 4 })).to_request()).await;
+    assert_eq!(picks.as_array().map(Vec::len), Some(4));
+}
+
+
+#[actix_web::test]
+async fn max_items_applies_to_every_input_format() {
+    let harness = Harness::new(config(json!({ "max_items": 3 })));
+    let app = app!(harness);
+    let plain = |body: &str| {
+        test::TestRequest::post()
+            .uri("/generate_random_choose?count=1")
+            .insert_header((header::CONTENT_TYPE, "text/plain"))
+            .set_payload(body.to_string())
+            .to_request()
+    };
+    let ndjson = |body: &str| {
+        test::TestRequest::post()
+            .uri("/generate_random_choose?count=1")
+            .insert_header((header::CONTENT_TYPE, "application/x-ndjson"))
+            .set_payload(body.to_string())
+            .to_request()
+    };
+
+    // At the limit every format is accepted
+    data(&app, post("/generate_random_choose", json!({ "items": ["a", "b", "c"], "count": 1 })).to_request()).await;
+    data(&app, plain("a\nb\nc\n")).await;
+    data(&app, ndjson("1\n2\n3\n")).await;
+
+    let over = [
+        send(&app, post("/generate_random_choose", json!({ "items": ["a", "b", "c", "d"], "count": 1 })).to_request()).await,
+        send(&app, plain("a\nb\nc\nd\n")).await,
+        send(&app, ndjson("1\n2\n3\n4\n")).await,
+    ];
+    for (status, body) in over {
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["data"], "Too many items: more than max_items (3)");
+    }
+}
+
+
+// Picks of object items are tallied by their JSON text
+fn count_id(counts: &HashMap<String, usize>, id: &str) -> usize {
+    let field = format!("\"id\":\"{}\"", id);
+    counts.iter().filter(|(pick, _)| pick.contains(&field)).map(|(_, count)| count).sum()
+}
+
+
+#[actix_web::test]
+async fn recency_favours_newer_items() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let trials = 3000;
+    let now = chrono::Utc::now();
+    let hours_ago = |hours: i64| now - chrono::Duration::hours(hours);
+
+    // Weights 1, 0.5 and 0.5^20 with a one-hour half-life; "new" is an RFC 3339 string, the rest Unix seconds
+    let items = json!([
+        { "id": "new", "at": hours_ago(0).to_rfc3339() },
+        { "id": "hour", "at": hours_ago(1).timestamp() },
+        { "id": "stale", "at": hours_ago(20).timestamp() },
+    ]);
+    let body = json!({ "items": items, "count": 1, "recency_key": "at", "half_life_secs": 3600 });
+    let counts = tally(&app, || post("/generate_random_choose", body.clone()).to_request(), trials).await;
+
+    assert_share("new", count_id(&counts, "new"), trials, 2.0 / 3.0, 0.04);
+    assert_share("hour", count_id(&counts, "hour"), trials, 1.0 / 3.0, 0.04);
+    assert!(count_id(&counts, "stale") <= 2, "an item 20 half-lives old was picked {} times", count_id(&counts, "stale"));
+
+    // A future timestamp counts as age 0, so it weighs as much as a current one
+    let items = json!([{ "id": "future", "at": (now + chrono::Duration::hours(5)).timestamp() }, { "id": "now", "at": now.timestamp() }]);
+    let body = json!({ "items": items, "count": 1, "recency_key": "at", "half_life_secs": 3600 });
+    let counts = tally(&app, || post("/generate_random_choose", body.clone()).to_request(), 1000).await;
+    assert_share("future", count_id(&counts, "future"), 1000, 0.5, 0.06);
+
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": items, "count": 1, "recency_key": "at" })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "recency_key requires half_life_secs.");
+}
+
+
+#[actix_web::test]
+async fn shuffle_key_orders_deterministically_per_key() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items: Vec<String> = (0..20).map(|i| format!("item-{}", i)).collect();
+    let order = |key: &str, count: usize| post("/generate_random_choose", json!({ "items": items, "count": count, "shuffle_key": key })).to_request();
+
+    let full = data(&app, order("team-a", 20)).await;
+    assert_eq!(data(&app, order("team-a", 20)).await, full, "the same key gives the same order");
+    let mut sorted: Vec<String> = serde_json::from_value(full.clone()).unwrap();
+    sorted.sort_by_key(|item| item[5..].parse::<u32>().unwrap());
+    assert_eq!(sorted, items, "a full-length order is a permutation");
+    assert_ne!(full.as_array().unwrap().iter().map(|item| item.as_str().unwrap()).collect::<Vec<_>>(), items);
+
+    assert_ne!(data(&app, order("team-b", 20)).await, full, "another key gives another order");
+    assert_eq!(data(&app, order("team-a", 3)).await, json!(full.as_array().unwrap()[..3]));
+
+    let resp = test::call_service(&app, order("team-a", 3)).await;
+    assert_eq!(resp.headers().get("X-Entropy-Bits").unwrap(), "0.00");
+
+    for (body, message) in [
+        (json!({ "items": items, "count": 1, "shuffle_key": "" }), "shuffle_key must not be empty."),
+        (json!({ "items": items, "count": 1, "shuffle_key": "k", "weight_by_length": true }), "shuffle_key cannot be combined with weighting or strata_key."),
+    ] {
+        let (status, body) = send(&app, post("/generate_random_choose", body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["data"], message);
+    }
+}
+
+
+#[actix_web::test]
+async fn choose_many_draws_respect_count_and_membership() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items: Vec<String> = (0..10).map(|i| format!("item-{}", i)).collect();
+
+    let draws = data(&app, post("/choose_many", json!({ "items": items, "draws": 500, "count_per_draw": 3 })).to_request()).await;
+    let draws = draws.as_array().unwrap();
+    assert_eq!(draws.len(), 500);
+    for draw in draws {
+        let picks: HashSet<&str> = draw.as_array().unwrap().iter().map(|pick| pick.as_str().unwrap()).collect();
+        assert_eq!(picks.len(), 3, "{} has a repeat or the wrong length", draw);
+        assert!(picks.iter().all(|pick| items.iter().any(|item| item == pick)), "{}", draw);
+    }
+
+    // A zero weight is never drawn, and every draw still has two picks
+    let body = json!({ "items": ["a", "b", "c"], "draws": 300, "count_per_draw": 2, "weights_map": { "c": 0.0 } });
+    let draws = data(&app, post("/choose_many", body).to_request()).await;
+    for draw in draws.as_array().unwrap() {
+        let picks: HashSet<&str> = draw.as_array().unwrap().iter().map(|pick| pick.as_str().unwrap()).collect();
+        assert_eq!(picks, HashSet::from(["a", "b"]));
+    }
+
+    for (body, message) in [
+        (json!({ "items": items, "draws": 1, "count_per_draw": 11 }), "count_per_draw must be <= item count (10)."),
+        (json!({ "items": items, "draws": 0, "count_per_draw": 1 }), "Invalid draws: 0 (draws × count_per_draw must be 1–100000)"),
+    ] {
+        let (status, body) = send(&app, post("/choose_many", body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["data"], message);
+    }
+}
+
+
+// Timing comparison rather than a correctness check: cargo test -- --ignored --nocapture choose_many_beats
+#[actix_web::test]
+#[ignore]
+async fn choose_many_beats_repeated_single_calls() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items: Vec<String> = (0..1000).map(|i| format!("item-{}", i)).collect();
+    let weights: serde_json::Map<String, serde_json::Value> = items.iter().enumerate().map(|(i, item)| (item.clone(), json!(i + 1))).collect();
+    let draws = 2000;
+
+    let started = std::time::Instant::now();
+    data(&app, post("/choose_many", json!({ "items": items, "draws": draws, "count_per_draw": 5, "weights_map": weights })).to_request()).await;
+    let batched = started.elapsed();
+
+    let started = std::time::Instant::now();
+    for _ in 0..draws {
+        data(&app, post("/generate_random_choose", json!({ "items": items, "count": 5, "weights_map": weights })).to_request()).await;
+    }
+    let single = started.elapsed();
+
+    println!("{} draws: choose_many {:?}, single calls {:?}", draws, batched, single);
+    assert!(batched < single);
+}
+
+
+#[actix_web::test]
+async fn weight_pointer_reads_nested_weights() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let trials = 3000;
+    let items = json!([
+        { "id": "a", "meta": { "score": 6 } },
+        { "id": "b", "meta": { "score": 3.0 } },
+        { "id": "c", "meta": { "score": 1 } },
+    ]);
+
+    let body = json!({ "items": items, "count": 1, "weight_pointer": "/meta/score" });
+    let counts = tally(&app, || post("/generate_random_choose", body.clone()).to_request(), trials).await;
+    for (id, expected) in [("a", 0.6), ("b", 0.3), ("c", 0.1)] {
+        assert_share(id, count_id(&counts, id), trials, expected, 0.04);
+    }
+
+    let mut missing = items.clone();
+    missing[1]["meta"] = json!({ "score": "high" });
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": missing, "count": 1, "weight_pointer": "/meta/score" })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Item 1 has no number at weight_pointer \"/meta/score\".");
+}
+
+
+#[actix_web::test]
+async fn join_returns_one_string_that_splits_back() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items = ["red", "green", "blue", "cyan", "magenta"];
+
+    for separator in [",", " | "] {
+        for _ in 0..20 {
+            let joined = data(&app, post("/generate_random_choose", json!({ "items": items, "count": 3, "join": separator })).to_request()).await;
+            let picks: Vec<&str> = joined.as_str().unwrap().split(separator).collect();
+
+            assert_eq!(picks.len(), 3, "{}", joined);
+            assert!(picks.iter().all(|pick| items.contains(pick)), "{}", joined);
+            assert_eq!(picks.iter().collect::<HashSet<_>>().len(), 3, "{}", joined);
+        }
+    }
+
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": ["a", 1], "count": 1, "join": "," })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "join requires every item to be a string.");
+}
+
+
+#[actix_web::test]
+async fn overflowing_weight_sums_are_rejected_everywhere() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let huge = json!({ "a": 1e308, "b": 1e308 });
+
+    for (path, body) in [
+        ("/generate_random_choose", json!({ "items": ["a", "b"], "count": 1, "weights_map": huge })),
+        ("/choose_many", json!({ "items": ["a", "b"], "draws": 2, "count_per_draw": 1, "weights_map": huge })),
+        ("/choose_histogram", json!({ "items": ["a", "b"], "trials": 10, "weights_map": huge })),
+        ("/loot", json!({ "entries": [{ "item": "a", "weight": 1e308 }, { "item": "b", "weight": 1e308 }] })),
+        ("/cohort", json!({ "user_id": "alice", "cohorts": huge })),
+    ] {
+        let (status, body) = send(&app, post(path, body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+        assert_eq!(body["data"], "Weights are too large: their sum is not a finite number.", "{}", path);
+    }
+}
+
+
+#[actix_web::test]
+async fn ranked_records_the_draw_order() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let items = ["a", "b", "c", "d", "e"];
+    let mut first_drawn = HashSet::new();
+
+    for _ in 0..30 {
+        let ranked = data(&app, post("/generate_random_choose", json!({ "items": items, "count": 5, "ranked": true })).to_request()).await;
+        let ranked = ranked.as_array().unwrap();
+
+        let ranks: Vec<u64> = ranked.iter().map(|entry| entry["rank"].as_u64().unwrap()).collect();
+        assert_eq!(ranks, [1, 2, 3, 4, 5]);
+        let picks: HashSet<&str> = ranked.iter().map(|entry| entry["item"].as_str().unwrap()).collect();
+        assert_eq!(picks, HashSet::from(items));
+        first_drawn.insert(ranked[0]["item"].as_str().unwrap().to_string());
+    }
+    assert!(first_drawn.len() > 1, "rank 1 went to {:?} every time", first_drawn);
+
+    // The heavy item is almost always drawn first, so it almost always holds rank 1
+    let body = json!({ "items": ["heavy", "x", "y"], "count": 3, "ranked": true, "weights_map": { "heavy": 100.0 } });
+    let mut heavy_first = 0;
+    for _ in 0..50 {
+        let ranked = data(&app, post("/generate_random_choose", body.clone()).to_request()).await;
+        heavy_first += usize::from(ranked[0]["item"] == "heavy");
+    }
+    assert!(heavy_first >= 45, "heavy was ranked first {} of 50 times", heavy_first);
+
+    let (status, body) = send(&app, post("/generate_random_choose", json!({ "items": items, "count": 2, "ranked": true, "join": "," })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "ranked cannot be combined with join or strata_key.");
+}
+
+
+#[actix_web::test]
+async fn coords_stay_inside_the_box() {
+    let mut harness = Harness::new(config(json!({})));
+    let _logs = harness.capture_logs("warn");
+    let app = app!(harness);
+
+    for on_sphere in [false, true] {
+        for (min_lat, max_lat, min_lon, max_lon) in [(10.0, 20.0, -5.0, 5.0), (80.0, 90.0, 170.0, 180.0), (-90.0, 90.0, -180.0, 180.0)] {
+            let body = json!({ "min_lat": min_lat, "max_lat": max_lat, "min_lon": min_lon, "max_lon": max_lon, "count": 500, "on_sphere": on_sphere });
+            let points = data(&app, post("/generate_coords", body).to_request()).await;
+            let points = points.as_array().unwrap();
+            assert_eq!(points.len(), 500);
+            for point in points {
+                let (lat, lon) = (point["lat"].as_f64().unwrap(), point["lon"].as_f64().unwrap());
+                assert!((min_lat..=max_lat).contains(&lat), "on_sphere {}: {}", on_sphere, point);
+                assert!((min_lon..=max_lon).contains(&lon), "on_sphere {}: {}", on_sphere, point);
+            }
+        }
+    }
+
+    // Over 0–90, the band below 45 degrees holds sin(45°) of the area, against half of the rectangle
+    let trials = 4000;
+    for (on_sphere, expected) in [(false, 0.5), (true, std::f64::consts::FRAC_1_SQRT_2)] {
+        let body = json!({ "min_lat": 0.0, "max_lat": 90.0, "min_lon": 0.0, "max_lon": 1.0, "count": trials, "on_sphere": on_sphere });
+        let points = data(&app, post("/generate_coords", body).to_request()).await;
+        let low = points.as_array().unwrap().iter().filter(|point| point["lat"].as_f64().unwrap() < 45.0).count();
+        assert_share(&format!("on_sphere {}", on_sphere), low, trials, expected, 0.03);
+    }
+
+    for body in [
+        json!({ "min_lat": 20.0, "max_lat": 10.0, "min_lon": 0.0, "max_lon": 1.0, "count": 1 }),
+        json!({ "min_lat": 0.0, "max_lat": 91.0, "min_lon": 0.0, "max_lon": 1.0, "count": 1 }),
+        json!({ "min_lat": 0.0, "max_lat": 1.0, "min_lon": -181.0, "max_lon": 1.0, "count": 1 }),
+        json!({ "min_lat": 0.0, "max_lat": 1.0, "min_lon": 0.0, "max_lon": 1.0, "count": 0 }),
+    ] {
+        let (status, _) = send(&app, post("/generate_coords", body.clone()).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+    let lines = harness.trace_buffer.snapshot();
+    assert!(lines.iter().any(|line| line.contains("WARN") && line.contains("coords_handler: min_lat must be less than max_lat")), "{:?}", lines);
+}
+
+
+#[actix_web::test]
+async fn clamp_count_caps_instead_of_rejecting() {
+    let items: Vec<String> = (0..200).map(|i| format!("item{}", i)).collect();
+    let choose = json!({ "items": items, "count": crate::MAX_COUNT + 50 });
+    let string = json!({ "use_digits": true, "length": crate::MAX_LENGTH + 10 });
+
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let (status, _) = send(&app, post("/generate_random_choose", choose.clone()).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, post("/generate_random_string", string.clone()).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let mut harness = Harness::new(config(json!({ "clamp_count": true })));
+    let _logs = harness.capture_logs("warn");
+    let app = app!(harness);
+
+    let picks = data(&app, post("/generate_random_choose", choose).to_request()).await;
+    assert_eq!(picks.as_array().unwrap().len(), crate::MAX_COUNT);
+    let value = data(&app, post("/generate_random_string", string).to_request()).await;
+    assert_eq!(value.as_str().unwrap().len(), crate::MAX_LENGTH);
+
+    let lines = harness.trace_buffer.snapshot();
+    assert_eq!(lines.iter().filter(|line| line.contains("WARN") && line.contains("clamp_count")).count(), 2, "{:?}", lines);
+
+    // Only the maxima are clamped; count above the number of items is still an error
+    let (status, _) = send(&app, post("/generate_random_choose", json!({ "items": ["a", "b"], "count": 3 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn inverse_frequency_gives_each_value_equal_odds() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let trials = 2000;
+
+    // Nine copies of "common" against one "rare": without the flag "rare" would come up 10% of the time
+    let mut items = vec!["common"; 9];
+    items.push("rare");
+    let counts = tally(&app, || post("/generate_random_choose", json!({ "items": items, "count": 1, "inverse_frequency": true })).to_request(), trials).await;
+    assert_share("common", counts["common"], trials, 0.5, 0.05);
+    assert_share("rare", counts["rare"], trials, 0.5, 0.05);
+
+    let (status, _) = send(&app, post("/generate_random_choose", json!({ "items": ["a", "b"], "count": 1, "inverse_frequency": true, "weight_by_length": true })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn partial_permutation_draws_distinct_values_without_the_full_range() {
+    let mut harness = Harness::new(config(json!({})));
+    let _logs = harness.capture_logs("warn");
+    let app = app!(harness);
+
+    // Materializing 0..u64::MAX would never finish, so this returning at all shows the range isn't allocated
+    let values = data(&app, post("/partial_permutation", json!({ "n": u64::MAX, "k": 1000 })).to_request()).await;
+    let values: Vec<u64> = values.as_array().unwrap().iter().map(|value| value.as_u64().unwrap()).collect();
+    assert_eq!(values.len(), 1000);
+    assert_eq!(values.iter().collect::<HashSet<_>>().len(), 1000, "values must be distinct");
+    assert!(values.iter().all(|&value| value < u64::MAX));
+
+    // k = n is a full permutation
+    let values = data(&app, post("/partial_permutation", json!({ "n": 10, "k": 10 })).to_request()).await;
+    let mut values: Vec<u64> = values.as_array().unwrap().iter().map(|value| value.as_u64().unwrap()).collect();
+    values.sort_unstable();
+    assert_eq!(values, (0..10).collect::<Vec<u64>>());
+
+    for body in [json!({ "n": 3, "k": 4 }), json!({ "n": 10, "k": 0 })] {
+        let (status, _) = send(&app, post("/partial_permutation", body.clone()).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+    let lines = harness.trace_buffer.snapshot();
+    assert!(lines.iter().any(|line| line.contains("WARN") && line.contains("partial_permutation_handler: k must be <= n.")), "{:?}", lines);
+}
+
+
+#[actix_web::test]
+async fn rejected_requests_are_logged_under_their_handler() {
+    let mut harness = Harness::new(config(json!({})));
+    let _logs = harness.capture_logs("warn");
+    let app = app!(harness);
+
+    let (status, _) = send(&app, post("/choose_many", json!({ "items": ["a", "b"], "draws": 2, "count_per_draw": 0 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, post("/generate_random_choose", json!({ "items": ["a"], "count": 2 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let lines = harness.trace_buffer.snapshot();
+    let logged = |target: &str| lines.iter().any(|line| line.contains("WARN") && line.contains(target));
+    assert!(logged("choose_many_handler: Invalid count_per_draw: 0"), "{:?}", lines);
+    assert!(logged("choose_handler"), "{:?}", lines);
+}
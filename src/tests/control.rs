@@ -0,0 +1,362 @@
+use actix_web::{http::{header, StatusCode}, test};
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use super::{app, config, data, hold, post, send, Harness};
+use crate::concurrency::InFlightLimit;
+
+
+
+#[actix_web::test]
+async fn ui_is_served_as_html_with_its_forms() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let content_type = resp.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap().to_string();
+    assert!(content_type.starts_with("text/html"), "{}", content_type);
+
+    let page = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    for id in ["generate-form", "use_digits", "length", "generate-result", "choose-form", "items", "count", "choose-result"] {
+        assert!(page.contains(&format!("id=\"{}\"", id)), "missing element #{}", id);
+    }
+}
+
+
+#[actix_web::test]
+async fn config_is_admin_gated() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let (status, body) = send(&app, test::TestRequest::get().uri("/config").to_request()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["success"], false);
+}
+
+
+#[actix_web::test]
+async fn config_shows_loaded_values_with_secrets_redacted() {
+    let harness = Harness::new(config(json!({ "allow_admin_endpoints": true, "workers_count": 3, "min_length": 12, "pepper": "hush", "signing_key": "c2VjcmV0" })));
+    let app = app!(harness);
+
+    let config = data(&app, test::TestRequest::get().uri("/config").to_request()).await;
+    assert_eq!(config["workers_count"], 3);
+    assert_eq!(config["min_length"], 12);
+    assert_eq!(config["name_for_port_manager"], "random_module_test");
+    assert_eq!(config["pepper"], "***");
+    assert_eq!(config["signing_key"], "***");
+    assert!(!config.to_string().contains("hush"));
+}
+
+
+#[actix_web::test]
+async fn ready_flips_while_the_concurrency_limit_is_saturated() {
+    let harness = Harness::new(config(json!({ "max_concurrent_requests": 1 })));
+    let mut release = None;
+    let app = test::init_service(
+        actix_web::App::new()
+            .configure(|cfg| harness.register(cfg))
+            .wrap(InFlightLimit::new(harness.in_flight.clone().into_inner(), Some(1), None))
+            .configure(|cfg| release = Some(hold(cfg)))
+            .configure(crate::routes),
+    )
+    .await;
+    let ready = || test::TestRequest::get().uri("/ready").to_request();
+
+    assert_eq!(send(&app, ready()).await.0, StatusCode::OK);
+
+    let held = test::call_service(&app, test::TestRequest::get().uri("/hold").to_request());
+    let probe = async {
+        // The held request is polled first, so its slot is taken by now
+        assert_eq!(harness.in_flight.current(), 1);
+        let (status, body) = send(&app, ready()).await;
+        release.take().unwrap().send(()).unwrap();
+        (status, body)
+    };
+    let (held, (status, body)) = futures::join!(held, probe);
+
+    assert_eq!(held.status(), StatusCode::OK);
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, json!({ "success": false, "data": "at capacity" }));
+    assert_eq!(send(&app, ready()).await.0, StatusCode::OK);
+}
+
+
+#[actix_web::test]
+async fn per_ip_limit_rejects_only_the_busy_address() {
+    let harness = Harness::new(config(json!({})));
+    let mut release = None;
+    let app = test::init_service(
+        actix_web::App::new()
+            .configure(|cfg| harness.register(cfg))
+            .wrap(InFlightLimit::new(harness.in_flight.clone().into_inner(), None, Some(1)))
+            .configure(|cfg| release = Some(hold(cfg)))
+            .configure(crate::routes),
+    )
+    .await;
+    let from = |ip: &str| test::TestRequest::get().uri("/status").peer_addr(format!("{}:40000", ip).parse().unwrap()).to_request();
+
+    let held = test::call_service(&app, test::TestRequest::get().uri("/hold").peer_addr("10.0.0.1:40000".parse().unwrap()).to_request());
+    let others = async {
+        let busy = [send(&app, from("10.0.0.1")).await, send(&app, from("10.0.0.1")).await];
+        let other = send(&app, from("10.0.0.2")).await.0;
+        release.take().unwrap().send(()).unwrap();
+        (busy, other)
+    };
+    let (held, (busy, other)) = futures::join!(held, others);
+
+    assert_eq!(held.status(), StatusCode::OK);
+    for (status, body) in busy {
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["data"], "Too many concurrent requests from this address, retry later");
+    }
+    assert_eq!(other, StatusCode::OK);
+
+    // The slot is returned once the held request completes
+    assert_eq!(send(&app, from("10.0.0.1")).await.0, StatusCode::OK);
+    assert_eq!(harness.in_flight.current(), 0);
+}
+
+
+#[actix_web::test]
+async fn stop_is_refused_unless_allowed() {
+    let mut harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let (status, body) = send(&app, test::TestRequest::get().uri("/stop").to_request()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["success"], false);
+    assert!(harness.stop_requests.try_recv().is_err(), "a refused /stop must not signal shutdown");
+
+    let mut harness = Harness::new(config(json!({ "allow_remote_stop": true })));
+    let app = app!(harness);
+
+    let (status, body) = send(&app, test::TestRequest::get().uri("/stop").to_request()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "success": true, "data": null }));
+    assert!(harness.stop_requests.try_recv().is_ok());
+}
+
+
+#[actix_web::test]
+async fn log_level_filters_and_changes_at_runtime() {
+    let mut harness = Harness::new(config(json!({ "allow_admin_endpoints": true })));
+    let _logs = harness.capture_logs("warn");
+    let app = app!(harness);
+
+    info!(target: "probe", "first info line");
+    warn!(target: "probe", "first warn line");
+    let lines = harness.trace_buffer.snapshot();
+    assert!(lines.iter().any(|line| line.contains("first warn line")), "{:?}", lines);
+    assert!(!lines.iter().any(|line| line.contains("first info line")), "warn must suppress info: {:?}", lines);
+
+    let (status, body) = send(&app, post("/log_level", json!({ "level": "info" })).to_request()).await;
+    assert_eq!((status, body), (StatusCode::OK, json!({ "success": true, "data": "info" })));
+
+    info!(target: "probe", "second info line");
+    let lines = harness.trace_buffer.snapshot();
+    assert!(lines.iter().any(|line| line.contains("second info line")), "{:?}", lines);
+
+    let (status, _) = send(&app, post("/log_level", json!({ "level": "not a level==" })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn log_level_is_admin_gated() {
+    let mut harness = Harness::new(config(json!({})));
+    let _logs = harness.capture_logs("warn");
+    let app = app!(harness);
+
+    let (status, body) = send(&app, post("/log_level", json!({ "level": "debug" })).to_request()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["success"], false);
+
+    debug!(target: "probe", "debug line");
+    let lines = harness.trace_buffer.snapshot();
+    assert!(!lines.iter().any(|line| line.contains("debug line")), "{:?}", lines);
+}
+
+
+
+#[actix_web::test]
+async fn limits_report_defaults_and_configured_values() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let limits = data(&app, test::TestRequest::get().uri("/self/limits").to_request()).await;
+
+    assert_eq!(limits["max_length"], crate::MAX_LENGTH);
+    assert_eq!(limits["max_count"], crate::MAX_COUNT);
+    assert_eq!(limits["min_length"], 1);
+    assert_eq!(limits["min_charset_size"], crate::DEFAULT_MIN_CHARSET_SIZE);
+    assert_eq!(limits["max_json_depth"], crate::DEFAULT_MAX_JSON_DEPTH);
+    assert_eq!(limits["max_body_bytes"], crate::DEFAULT_MAX_BODY_BYTES);
+    assert_eq!(limits["max_items"], json!(null));
+    assert_eq!(limits["max_concurrent_requests"], json!(null));
+
+    let harness = Harness::new(config(json!({ "min_length": 8, "min_charset_size": 4, "max_items": 500, "max_json_depth": 3, "max_body_bytes": 1024, "max_concurrent_requests": 16 })));
+    let app = app!(harness);
+    let limits = data(&app, test::TestRequest::get().uri("/self/limits").to_request()).await;
+
+    assert_eq!(limits["min_length"], 8);
+    assert_eq!(limits["min_charset_size"], 4);
+    assert_eq!(limits["max_items"], 500);
+    assert_eq!(limits["max_json_depth"], 3);
+    assert_eq!(limits["max_body_bytes"], 1024);
+    assert_eq!(limits["max_concurrent_requests"], 16);
+}
+
+
+#[actix_web::test]
+async fn trace_returns_recent_log_lines_with_secrets_redacted() {
+    let mut harness = Harness::new(config(json!({ "allow_admin_endpoints": true, "pepper": "hunter2-pepper" })));
+    let _logs = harness.capture_logs("info");
+    let app = app!(harness);
+
+    data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8 })).to_request()).await;
+    info!(target: "probe", "pepper is hunter2-pepper");
+
+    let lines = data(&app, test::TestRequest::get().uri("/trace").to_request()).await;
+    let lines: Vec<&str> = lines.as_array().unwrap().iter().map(|line| line.as_str().unwrap()).collect();
+    assert!(lines.iter().any(|line| line.contains("Generation completed")), "{:?}", lines);
+    assert!(lines.iter().any(|line| line.contains("pepper is ***")), "{:?}", lines);
+    assert!(!lines.iter().any(|line| line.contains("hunter2")), "{:?}", lines);
+}
+
+
+#[actix_web::test]
+async fn log_sample_rate_zero_drops_handler_info_but_keeps_warnings() {
+    use actix_web::dev::Service;
+
+    let mut harness = Harness::new(config(json!({ "log_sample_rate": 0.0 })));
+    let _logs = harness.capture_logs("info");
+    let rate = harness.config.log_sample_rate;
+    let app = test::init_service(
+        actix_web::App::new()
+            .wrap_fn(move |req, srv| crate::log_sampling::sample_request(srv.call(req), rate))
+            .configure(|cfg| harness.register(cfg))
+            .configure(crate::routes),
+    )
+    .await;
+
+    data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8 })).to_request()).await;
+    let lines = harness.trace_buffer.snapshot();
+    assert!(!lines.iter().any(|line| line.contains("generate_handler")), "unsampled info must be dropped: {:?}", lines);
+
+    let (status, _) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 0 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let lines = harness.trace_buffer.snapshot();
+    assert!(lines.iter().any(|line| line.contains("WARN") && line.contains("generate_handler")), "{:?}", lines);
+    assert!(!lines.iter().any(|line| line.contains("INFO") && line.contains("generate_handler")), "{:?}", lines);
+
+    // Outside a request the flag is clear, so other info lines are kept
+    info!(target: "probe_handler", "outside any request");
+    assert!(harness.trace_buffer.snapshot().iter().any(|line| line.contains("outside any request")));
+}
+
+
+#[actix_web::test]
+async fn trace_is_admin_gated() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let (status, body) = send(&app, test::TestRequest::get().uri("/trace").to_request()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["success"], false);
+}
+
+
+#[actix_web::test]
+async fn cache_clear_empties_every_cache() {
+    let harness = Harness::new(config(json!({ "allow_admin_endpoints": true })));
+    let app = app!(harness);
+
+    for body in [
+        json!({ "use_digits": true, "length": 16, "dedup_by_body": true }),
+        json!({ "use_digits": true, "length": 16, "seed": 7 }),
+        json!({ "use_digits": true, "length": 16, "seed": 8 }),
+        json!({ "use_digits": true, "length": 16, "avoid_recent": true }),
+    ] {
+        data(&app, post("/generate_random_string", body).to_request()).await;
+    }
+
+    let cleared = data(&app, post("/admin/cache/clear", json!({})).to_request()).await;
+    assert_eq!(cleared, json!({ "dedup_by_body": 1, "seeded": 2, "avoid_recent": 1, "total": 4 }));
+
+    let cleared = data(&app, post("/admin/cache/clear", json!({})).to_request()).await;
+    assert_eq!(cleared["total"], 0);
+}
+
+
+#[actix_web::test]
+async fn cache_clear_is_admin_gated() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 16, "seed": 7 })).to_request()).await;
+    let (status, _) = send(&app, post("/admin/cache/clear", json!({})).to_request()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(harness.seeded_cache.lock().unwrap().clear(), 1, "a refused clear leaves the caches alone");
+}
+
+
+#[actix_web::test]
+async fn slow_requests_show_up_in_metrics() {
+    let harness = Harness::new(config(json!({ "slow_request_threshold_ms": 0 })));
+    let app = app!(harness);
+
+    let metrics = data(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+    assert_eq!(metrics, json!({ "slow_requests": 0, "slow_request_threshold_ms": 0 }));
+
+    // Weighing and sampling 100k items takes well over a millisecond
+    let items: Vec<String> = (0..100_000).map(|i| format!("item-{}", i)).collect();
+    data(&app, post("/generate_random_choose", json!({ "items": items, "count": 100, "weight_by_length": true })).to_request()).await;
+
+    let metrics = data(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+    assert_eq!(metrics["slow_requests"], 1);
+}
+
+
+#[actix_web::test]
+async fn route_prefix_moves_every_route() {
+    let get = |path: &str| test::TestRequest::get().uri(path).to_request();
+
+    for probes_at_root in [false, true] {
+        let harness = Harness::new(config(json!({})));
+        let app = test::init_service(
+            actix_web::App::new()
+                .configure(|cfg| harness.register(cfg))
+                .configure(|cfg| crate::mount(cfg, "/random", probes_at_root)),
+        )
+        .await;
+
+        assert_eq!(send(&app, get("/random/status")).await.0, StatusCode::OK);
+        assert_eq!(send(&app, get("/random/healthz")).await.0, StatusCode::OK);
+        assert_eq!(send(&app, get("/status")).await.0, StatusCode::NOT_FOUND);
+
+        let root_probe = if probes_at_root { StatusCode::OK } else { StatusCode::NOT_FOUND };
+        assert_eq!(send(&app, get("/healthz")).await.0, root_probe);
+        assert_eq!(send(&app, get("/ready")).await.0, root_probe);
+    }
+
+    // The normalized form of "random/" and "/random"
+    assert_eq!(config(json!({ "route_prefix": "random/" })).route_prefix(), "/random");
+    assert_eq!(config(json!({})).route_prefix(), "");
+}
+
+
+#[actix_web::test]
+async fn unserializable_data_gets_a_structured_500() {
+    // JSON object keys must be strings
+    let resp = crate::success(std::collections::HashMap::from([((1, 2), "pair")]));
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body: serde_json::Value = serde_json::from_slice(&actix_web::body::to_bytes(resp.into_body()).await.unwrap()).unwrap();
+    assert_eq!(body, json!({ "success": false, "data": "Response could not be serialized" }));
+
+    // Non-finite floats are written as null rather than failing
+    let resp = crate::success([1.5, f64::NAN, f64::INFINITY]);
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&actix_web::body::to_bytes(resp.into_body()).await.unwrap()).unwrap();
+    assert_eq!(body, json!({ "success": true, "data": [1.5, null, null] }));
+}
@@ -0,0 +1,820 @@
+use actix_web::{http::{header, StatusCode}, test};
+use serde_json::json;
+use std::collections::HashSet;
+
+use super::{app, config, data, post, send, Harness};
+use crate::policy::PasswordPolicy;
+use crate::random_module::CharClass;
+
+
+
+#[actix_web::test]
+async fn policy_holds_in_every_output() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let policy = json!({ "min_length": 12, "min_digits": 3, "min_lowercase": 2, "min_uppercase": 2, "min_special": 2 });
+
+    for _ in 0..50 {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 12, "policy": policy });
+        let result = data(&app, post("/generate_random_string", body).to_request()).await;
+
+        let value = result["value"].as_str().unwrap();
+        assert_eq!(value.len(), 12);
+        assert_eq!(result["policy"], policy);
+        let policy: PasswordPolicy = serde_json::from_value(policy.clone()).unwrap();
+        assert!(policy.is_satisfied_by(value), "{} violates the policy", value);
+    }
+}
+
+
+#[actix_web::test]
+async fn unsatisfiable_policy_is_rejected() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    // More classed characters than the length, and a class whose charset is disabled
+    for (flags, policy) in [
+        (json!({ "use_digits": true, "use_lowercase": true }), json!({ "min_digits": 3, "min_lowercase": 3 })),
+        (json!({ "use_lowercase": true }), json!({ "min_digits": 1 })),
+    ] {
+        let mut body = flags;
+        body["length"] = json!(4);
+        body["policy"] = policy;
+
+        let (status, body) = send(&app, post("/generate_random_string", body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["success"], false);
+        assert!(body["data"].as_str().unwrap().starts_with("Unsatisfiable policy"), "{}", body);
+    }
+}
+
+
+#[actix_web::test]
+async fn max_classes_limits_the_classes_in_the_output() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let mut seen_classes = HashSet::new();
+
+    for _ in 0..50 {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 32, "max_classes": 2 });
+        let value = data(&app, post("/generate_random_string", body).to_request()).await;
+
+        let classes: HashSet<CharClass> = value.as_str().unwrap().chars().map(CharClass::of).collect();
+        assert!(classes.len() <= 2, "{} uses {} classes", value, classes.len());
+        seen_classes.extend(classes);
+    }
+
+    // The subset is picked per request, so across requests every class shows up
+    assert_eq!(seen_classes.len(), 4);
+}
+
+
+#[actix_web::test]
+async fn max_classes_keeps_the_classes_a_policy_requires() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for _ in 0..20 {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 16, "max_classes": 1, "policy": { "min_special": 1 } });
+        let result = data(&app, post("/generate_random_string", body).to_request()).await;
+        assert!(result["value"].as_str().unwrap().chars().all(|c| CharClass::of(c) == CharClass::Special), "{}", result);
+    }
+
+    let body = json!({ "use_digits": true, "use_spec": true, "length": 16, "max_classes": 1, "policy": { "min_digits": 1, "min_special": 1 } });
+    let (status, _) = send(&app, post("/generate_random_string", body).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn exported_seed_and_counter_replay_the_value() {
+    // Without the seeded cache, so the replay really regenerates
+    let harness = Harness::new(config(json!({ "seeded_cache_size": 0 })));
+    let app = app!(harness);
+    let flags = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "length": 24 });
+
+    let mut body = flags.clone();
+    body["export_seed"] = json!(true);
+    body["counter"] = json!(5);
+    let first = data(&app, post("/generate_random_string", body).to_request()).await;
+    assert_eq!(first["counter"], 5);
+    let seed = first["seed"].as_u64().expect("seed is exported");
+
+    let mut replay = flags.clone();
+    replay["seed"] = json!(seed);
+    replay["counter"] = json!(5);
+    let replayed = data(&app, post("/generate_random_string", replay.clone()).to_request()).await;
+    assert_eq!(replayed, first["value"]);
+
+    replay["counter"] = json!(6);
+    let next = data(&app, post("/generate_random_string", replay).to_request()).await;
+    assert_ne!(next, first["value"], "another counter is another stream");
+}
+
+
+#[actix_web::test]
+async fn syllable_pattern_is_followed_position_by_position() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for pattern in ["CVC", "CV", "CVCC"] {
+        let body = json!({ "syllable_pattern": pattern, "length": 10 });
+        let value = data(&app, post("/generate_random_string", body).to_request()).await;
+        let value = value.as_str().unwrap();
+
+        assert_eq!(value.len(), 10);
+        for (c, slot) in value.chars().zip(pattern.chars().cycle()) {
+            let vowel = "aeiou".contains(c);
+            assert!(c.is_ascii_lowercase() && vowel == (slot == 'V'), "{} does not follow {}", value, pattern);
+        }
+    }
+
+    let (status, _) = send(&app, post("/generate_random_string", json!({ "syllable_pattern": "CXV", "length": 6 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn non_json_bodies_get_a_415_envelope() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for (path, content_type) in [
+        ("/generate_random_string", "text/plain"),
+        ("/generate", "text/plain"),
+        ("/generate_random_choose", "application/x-www-form-urlencoded"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri(path)
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload("length=8")
+            .to_request();
+        let (status, body) = send(&app, req).await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE, "{}", path);
+        assert_eq!(body, json!({ "success": false, "data": "Content-Type must be application/json" }));
+    }
+
+    // Malformed JSON with the right type is a 400 in the same envelope
+    let req = test::TestRequest::post().uri("/generate_random_string").insert_header((header::CONTENT_TYPE, "application/json")).set_payload("{").to_request();
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["success"], false);
+}
+
+
+#[actix_web::test]
+async fn min_length_rejects_shorter_requests() {
+    let harness = Harness::new(config(json!({ "min_length": 12 })));
+    let app = app!(harness);
+    let request = |length| post("/generate_random_string", json!({ "use_lowercase": true, "length": length })).to_request();
+
+    let (status, body) = send(&app, request(11)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Length too short: 11 (minimum is 12)");
+
+    let value = data(&app, request(12)).await;
+    assert_eq!(value.as_str().map(str::len), Some(12));
+
+    // The ceiling keeps its own message
+    let (status, body) = send(&app, request(257)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["data"].as_str().unwrap().starts_with("Invalid length"), "{}", body);
+}
+
+
+#[actix_web::test]
+async fn min_length_defaults_to_one() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let (status, body) = send(&app, post("/generate_random_string", json!({ "use_lowercase": true, "length": 0 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Length too short: 0 (minimum is 1)");
+
+    let value = data(&app, post("/generate_random_string", json!({ "use_lowercase": true, "length": 1 })).to_request()).await;
+    assert_eq!(value.as_str().map(str::len), Some(1));
+}
+
+
+#[actix_web::test]
+async fn entropy_header_matches_the_charset_and_length() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let entropy = |resp: &actix_web::dev::ServiceResponse| resp.headers().get("X-Entropy-Bits").map(|value| value.to_str().unwrap().to_string());
+
+    // 10 digits: 10 * log2(10)
+    let resp = test::call_service(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 10 })).to_request()).await;
+    assert_eq!(entropy(&resp).as_deref(), Some("33.22"));
+
+    // 2 distinct picks out of 8: log2(8) + log2(7)
+    let resp = test::call_service(&app, post("/generate_random_choose", json!({ "items": [1, 2, 3, 4, 5, 6, 7, 8], "count": 2 })).to_request()).await;
+    assert_eq!(entropy(&resp).as_deref(), Some("5.81"));
+
+    // Errors carry no accounting
+    let resp = test::call_service(&app, post("/generate_random_string", json!({ "length": 10 })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(entropy(&resp), None);
+}
+
+
+#[actix_web::test]
+async fn identifier_safe_output_is_an_identifier() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for _ in 0..50 {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 20, "identifier_safe": true });
+        let value = data(&app, post("/generate_random_string", body).to_request()).await;
+        let value = value.as_str().unwrap();
+
+        // ^[A-Za-z][A-Za-z0-9_]*$
+        assert_eq!(value.len(), 20);
+        assert!(value.starts_with(|c: char| c.is_ascii_alphabetic()), "{}", value);
+        assert!(value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "{}", value);
+    }
+
+    let (status, body) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8, "identifier_safe": true })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["data"].as_str().unwrap().starts_with("identifier_safe requires"), "{}", body);
+}
+
+
+#[actix_web::test]
+async fn avoid_recent_does_not_repeat_within_the_window() {
+    let harness = Harness::new(config(json!({ "recent_window": 25 })));
+    let app = app!(harness);
+    // Only 100 possible values, so 25 unguarded draws would very likely repeat one
+    let body = json!({ "use_digits": true, "length": 2, "avoid_recent": true });
+
+    data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 2 })).to_request()).await;
+    assert_eq!(harness.recent.lock().unwrap().clear(), 0, "values not opted in are never remembered");
+
+    let mut seen = HashSet::new();
+    for _ in 0..25 {
+        let value = data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+        assert!(seen.insert(value.as_str().unwrap().to_string()), "{} repeated within the window", value);
+    }
+
+    // Past the window the oldest value is forgotten
+    data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+    assert_eq!(harness.recent.lock().unwrap().clear(), 25);
+}
+
+
+#[actix_web::test]
+async fn chaos_error_rate_fails_exactly_the_configured_fraction() {
+    let body = json!({ "use_lowercase": true, "length": 8 });
+
+    let harness = Harness::new(config(json!({ "chaos_error_rate": 1.0 })));
+    let app = app!(harness);
+    for _ in 0..20 {
+        let (status, response) = send(&app, post("/generate_random_string", body.clone()).to_request()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response, json!({ "success": false, "data": crate::CHAOS_MARKER }));
+    }
+
+    let harness = Harness::new(config(json!({ "chaos_error_rate": 0.0 })));
+    let app = app!(harness);
+    for _ in 0..20 {
+        data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+    }
+}
+
+
+#[actix_web::test]
+async fn min_response_ms_is_a_floor_for_success_and_error() {
+    let harness = Harness::new(config(json!({ "min_response_ms": 120 })));
+    let app = app!(harness);
+    let floor = std::time::Duration::from_millis(120);
+
+    let started = std::time::Instant::now();
+    let value = data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 12 })).to_request()).await;
+    assert!(started.elapsed() >= floor, "answered after {:?}", started.elapsed());
+    let value = value.as_str().unwrap();
+    assert!(value.len() == 12 && value.bytes().all(|b| b.is_ascii_digit()), "{}", value);
+
+    let started = std::time::Instant::now();
+    let (status, _) = send(&app, post("/generate_random_string", json!({ "length": 12 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(started.elapsed() >= floor, "error answered after {:?}", started.elapsed());
+}
+
+
+#[actix_web::test]
+async fn charset_presets_stay_within_their_alphabet() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let generate = |preset: &str| post("/generate_random_string", json!({ "charset_preset": preset, "use_spec": true, "length": 64 })).to_request();
+
+    for _ in 0..30 {
+        let value = data(&app, generate("base58")).await;
+        let value = value.as_str().unwrap();
+        assert!(value.chars().all(|c| c.is_ascii_alphanumeric() && !"0OIl".contains(c)), "{}", value);
+
+        // The preset overrides the class flags, so use_spec adds nothing
+        let value = data(&app, generate("hex")).await;
+        let value = value.as_str().unwrap();
+        assert!(value.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')), "{}", value);
+    }
+
+    let (status, _) = send(&app, generate("base64")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn dedup_by_body_repeats_the_result_for_an_identical_body() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let body = json!({ "use_lowercase": true, "use_digits": true, "length": 32, "dedup_by_body": true });
+
+    let first = data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+    let again = data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+    assert_eq!(first, again);
+
+    let mut changed = body.clone();
+    changed["use_uppercase"] = json!(true);
+    assert_ne!(data(&app, post("/generate_random_string", changed).to_request()).await, first);
+
+    let mut not_opted_in = body.clone();
+    not_opted_in["dedup_by_body"] = json!(false);
+    let one = data(&app, post("/generate_random_string", not_opted_in.clone()).to_request()).await;
+    assert_ne!(data(&app, post("/generate_random_string", not_opted_in).to_request()).await, one);
+
+    // Past the TTL the body generates anew
+    let harness = Harness::new(config(json!({ "dedup_ttl_secs": 0 })));
+    let app = app!(harness);
+    let first = data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+    assert_ne!(data(&app, post("/generate_random_string", body).to_request()).await, first);
+}
+
+
+#[actix_web::test]
+async fn every_encoding_decodes_back_to_the_value() {
+    use base64::Engine;
+
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 40, "encodings": ["utf8", "hex", "base64"] });
+
+    for _ in 0..10 {
+        let result = data(&app, post("/generate_random_string", body.clone()).to_request()).await;
+        let value = result["value"].as_str().unwrap().as_bytes();
+        let encodings = &result["encodings"];
+
+        assert_eq!(encodings["utf8"].as_str().unwrap().as_bytes(), value);
+        assert_eq!(hex::decode(encodings["hex"].as_str().unwrap()).unwrap(), value);
+        assert_eq!(base64::engine::general_purpose::STANDARD.decode(encodings["base64"].as_str().unwrap()).unwrap(), value);
+    }
+
+    let (status, _) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8, "encodings": ["base32"] })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn position_constraints_hold_at_their_positions() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let constraints = json!([
+        { "start": 0, "classes": ["lowercase", "uppercase"] },
+        { "start": 4, "end": 5, "classes": ["digits"] },
+    ]);
+
+    for _ in 0..50 {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 8, "position_constraints": constraints });
+        let value = data(&app, post("/generate_random_string", body).to_request()).await;
+        let value = value.as_str().unwrap().as_bytes();
+
+        assert_eq!(value.len(), 8);
+        assert!(value[0].is_ascii_alphabetic(), "{:?}", value);
+        assert!(value[4].is_ascii_digit() && value[5].is_ascii_digit(), "{:?}", value);
+    }
+
+    for (constraints, message) in [
+        (json!([{ "start": 6, "end": 8, "classes": ["digits"] }]), "Invalid position range 6–8 for length 8"),
+        (json!([{ "start": 2, "classes": ["special"] }]), "No enabled characters satisfy the constraints on position 2"),
+    ] {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "length": 8, "position_constraints": constraints });
+        let (status, body) = send(&app, post("/generate_random_string", body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["data"], message);
+    }
+}
+
+
+#[actix_web::test]
+async fn prefix_and_suffix_wrap_a_random_middle_of_length() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for _ in 0..20 {
+        let body = json!({ "use_lowercase": true, "use_digits": true, "length": 24, "prefix": "sk_live_", "suffix": "_v1" });
+        let value = data(&app, post("/generate_random_string", body).to_request()).await;
+        let value = value.as_str().unwrap();
+
+        let middle = value.strip_prefix("sk_live_").and_then(|rest| rest.strip_suffix("_v1")).unwrap_or_else(|| panic!("{} is not wrapped", value));
+        assert_eq!(middle.len(), 24);
+        assert!(middle.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()), "{}", middle);
+    }
+
+    let (status, _) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 4, "prefix": "x".repeat(257) })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+
+#[actix_web::test]
+async fn degenerate_charsets_are_rejected() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let digits_without = |denylist: &str| post("/generate_random_string", json!({ "use_digits": true, "length": 8, "denylist": denylist })).to_request();
+
+    let (status, body) = send(&app, digits_without("012345678")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Degenerate charset: 1 distinct character(s) after filtering, at least 2 required");
+
+    let value = data(&app, digits_without("01234567")).await;
+    assert!(value.as_str().unwrap().bytes().all(|b| b == b'8' || b == b'9'), "{}", value);
+
+    // The threshold is configurable
+    let harness = Harness::new(config(json!({ "min_charset_size": 11 })));
+    let app = app!(harness);
+    let (status, _) = send(&app, digits_without("")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn qr_alphanumeric_preset_stays_in_the_qr_set() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let mut seen = HashSet::new();
+
+    for _ in 0..50 {
+        let value = data(&app, post("/generate_random_string", json!({ "charset_preset": "qr_alphanumeric", "length": 64 })).to_request()).await;
+        let value = value.as_str().unwrap();
+        assert!(value.chars().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase() || " $%*+-./:".contains(c)), "{:?}", value);
+        seen.extend(value.chars());
+    }
+
+    assert!(!seen.iter().any(char::is_ascii_lowercase));
+    assert_eq!(seen.len(), 45, "every one of the 45 QR characters shows up over 3200 draws");
+}
+
+
+#[actix_web::test]
+async fn provenance_is_reported_only_when_requested() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let before = chrono::Utc::now();
+
+    let result = data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8, "provenance": true })).to_request()).await;
+    let provenance = &result["provenance"];
+    assert_eq!(provenance["algorithm"], "ChaCha20");
+    assert_eq!(provenance["crate_version"], env!("CARGO_PKG_VERSION"));
+    let generated_at = chrono::DateTime::parse_from_rfc3339(provenance["generated_at"].as_str().unwrap()).unwrap();
+    assert!(generated_at >= before && generated_at <= chrono::Utc::now());
+    assert_eq!(result["value"].as_str().map(str::len), Some(8));
+
+    for body in [json!({ "use_digits": true, "length": 8 }), json!({ "use_digits": true, "length": 8, "provenance": false })] {
+        let value = data(&app, post("/generate_random_string", body).to_request()).await;
+        assert!(value.is_string(), "no metadata block without provenance: {}", value);
+    }
+}
+
+
+#[actix_web::test]
+async fn retry_on_panic_gives_a_panicking_backend_one_more_try() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{with_panic_retry, ServiceError};
+
+    // Panics on the first `failures` calls, like generate_once it turns a caught panic into Internal
+    let backend = |calls: &AtomicUsize, failures: usize| {
+        std::panic::catch_unwind(|| {
+            if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                panic!("transient backend failure");
+            }
+            42
+        })
+        .map_err(|_| ServiceError::Internal("Panic occurred during generation"))
+    };
+
+    let calls = AtomicUsize::new(0);
+    let result = with_panic_retry(&config(json!({ "retry_on_panic": true })), "test", || backend(&calls, 1));
+    assert!(matches!(result, Ok(42)));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let calls = AtomicUsize::new(0);
+    let result = with_panic_retry(&config(json!({})), "test", || backend(&calls, 1));
+    assert!(matches!(result, Err(ServiceError::Internal(_))), "no retry unless enabled");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let calls = AtomicUsize::new(0);
+    let result = with_panic_retry(&config(json!({ "retry_on_panic": true })), "test", || backend(&calls, 5));
+    assert!(matches!(result, Err(ServiceError::Internal(_))), "only one retry");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+
+#[actix_web::test]
+async fn luhn_valid_output_passes_the_checksum() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    // Independent validator: double every second digit from the right, the sum must end in 0
+    let luhn = |value: &str| {
+        let sum: u32 = value.bytes().rev().enumerate().map(|(i, b)| {
+            let digit = (b - b'0') as u32;
+            if i % 2 == 1 { if digit * 2 > 9 { digit * 2 - 9 } else { digit * 2 } } else { digit }
+        }).sum();
+        sum.is_multiple_of(10)
+    };
+
+    for length in [2, 16, 19] {
+        for _ in 0..20 {
+            let value = data(&app, post("/generate_random_string", json!({ "length": length, "luhn_valid": true })).to_request()).await;
+            let value = value.as_str().unwrap();
+            assert_eq!(value.len(), length);
+            assert!(value.bytes().all(|b| b.is_ascii_digit()), "{}", value);
+            assert!(luhn(value), "{} fails the Luhn check", value);
+        }
+    }
+
+    assert_eq!(crate::random_module::luhn_check_digit(b"7992739871"), b'3');
+}
+
+
+#[actix_web::test]
+async fn generate_dispatches_on_type() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let value = data(&app, post("/generate", json!({ "type": "string", "use_digits": true, "length": 16 })).to_request()).await;
+    let value = value.as_str().unwrap();
+    assert_eq!(value.len(), 16);
+    assert!(value.bytes().all(|b| b.is_ascii_digit()), "{}", value);
+
+    let picks = data(&app, post("/generate", json!({ "type": "choose", "items": ["a", "b", "c"], "count": 2 })).to_request()).await;
+    let picks: Vec<&str> = picks.as_array().unwrap().iter().map(|pick| pick.as_str().unwrap()).collect();
+    assert_eq!(picks.len(), 2);
+    assert!(picks.iter().all(|pick| ["a", "b", "c"].contains(pick)), "{:?}", picks);
+
+    // Validation is the dedicated endpoint's
+    let (status, body) = send(&app, post("/generate", json!({ "type": "choose", "items": ["a"], "count": 2 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "Count must be <= item count.");
+
+    for body in [json!({ "type": "uuid" }), json!({ "type": "int", "min": 1, "max": 6 }), json!({ "length": 8 })] {
+        let (status, body) = send(&app, post("/generate", body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let message = body["data"].as_str().unwrap();
+        assert!(message.contains("string") && message.contains("choose"), "{} does not name the supported types", message);
+    }
+}
+
+
+#[actix_web::test]
+async fn failures_serialize_in_either_format() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let (status, body) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 0 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let message = body["data"].as_str().unwrap();
+    assert_eq!(body, json!({ "success": false, "data": message }));
+
+    // The flag is read once at startup, so the legacy shape is checked on the same message directly
+    assert_eq!(serde_json::to_value(crate::failure_in(true, message)).unwrap(), json!({ "error": message }));
+    assert_eq!(serde_json::to_value(crate::failure_in(false, message)).unwrap(), body);
+}
+
+
+#[actix_web::test]
+async fn only_seeded_requests_are_cached() {
+    let mut harness = Harness::new(config(json!({})));
+    let _logs = harness.capture_logs("info");
+    let app = app!(harness);
+    let hits = |harness: &Harness| harness.trace_buffer.snapshot().iter().filter(|line| line.contains("Returning cached result for a seeded request")).count();
+
+    let seeded = json!({ "use_lowercase": true, "length": 16, "seed": 439 });
+    let first = data(&app, post("/generate_random_string", seeded.clone()).to_request()).await;
+    assert_eq!(hits(&harness), 0);
+    let second = data(&app, post("/generate_random_string", seeded).to_request()).await;
+    assert_eq!(first, second);
+    assert_eq!(hits(&harness), 1, "the repeat is answered from the cache");
+
+    // A different seed is a different key
+    data(&app, post("/generate_random_string", json!({ "use_lowercase": true, "length": 16, "seed": 440 })).to_request()).await;
+    assert_eq!(hits(&harness), 1);
+
+    for _ in 0..3 {
+        data(&app, post("/generate_random_string", json!({ "use_lowercase": true, "length": 16 })).to_request()).await;
+        data(&app, post("/generate_random_string", json!({ "use_lowercase": true, "length": 16, "seed": 439, "provenance": true })).to_request()).await;
+    }
+    assert_eq!(hits(&harness), 1);
+    assert_eq!(harness.seeded_cache.lock().unwrap().clear(), 2, "only the two seeded requests were stored");
+}
+
+
+#[actix_web::test]
+async fn class_breakdown_counts_the_random_part() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let breakdown = |value: &str| serde_json::to_value(crate::ClassBreakdown::of(value)).unwrap();
+
+    assert_eq!(breakdown("aZ3k-9"), json!({ "digits": 2, "lowercase": 2, "uppercase": 1, "special": 1 }));
+    assert_eq!(breakdown("é "), json!({ "digits": 0, "lowercase": 0, "uppercase": 0, "special": 2 }));
+
+    for _ in 0..20 {
+        let body = json!({ "use_digits": true, "use_lowercase": true, "use_uppercase": true, "use_spec": true, "length": 20, "prefix": "ID-", "class_breakdown": true });
+        let result = data(&app, post("/generate_random_string", body).to_request()).await;
+        let value = result["value"].as_str().unwrap().strip_prefix("ID-").unwrap();
+
+        assert_eq!(result["class_breakdown"], breakdown(value));
+        let total: u64 = result["class_breakdown"].as_object().unwrap().values().map(|count| count.as_u64().unwrap()).sum();
+        assert_eq!(total, 20);
+    }
+}
+
+
+#[actix_web::test]
+async fn check_policy_reports_each_rule() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let policy = json!({ "min_length": 12, "min_digits": 1, "min_uppercase": 1, "min_special": 1 });
+
+    let report = data(&app, post("/check_policy", json!({ "value": "hunter2", "policy": policy })).to_request()).await;
+    assert_eq!(report["passed"], false);
+    assert_eq!(report["rules"], json!([
+        { "rule": "min_length", "required": 12, "actual": 7, "passed": false },
+        { "rule": "min_digits", "required": 1, "actual": 1, "passed": true },
+        { "rule": "min_lowercase", "required": 0, "actual": 6, "passed": true },
+        { "rule": "min_uppercase", "required": 1, "actual": 0, "passed": false },
+        { "rule": "min_special", "required": 1, "actual": 0, "passed": false },
+    ]));
+
+    let report = data(&app, post("/check_policy", json!({ "value": "Correct-Horse-7-Battery", "policy": policy })).to_request()).await;
+    assert_eq!(report["passed"], true);
+    assert!(report["rules"].as_array().unwrap().iter().all(|rule| rule["passed"] == true), "{}", report);
+
+    let (status, _) = send(&app, post("/check_policy", json!({ "policy": policy })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn denylist_removes_characters_from_any_charset() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for (options, denylist) in [
+        (json!({ "use_digits": true, "use_lowercase": true, "use_spec": true }), "&<>0a"),
+        (json!({ "charset_preset": "hex" }), "abcdef"),
+        (json!({ "charset_preset": "cyrillic" }), "абвгдАБВГД"),
+    ] {
+        for _ in 0..20 {
+            let mut body = options.clone();
+            body["length"] = json!(64);
+            body["denylist"] = json!(denylist);
+            let value = data(&app, post("/generate_random_string", body).to_request()).await;
+            let value = value.as_str().unwrap();
+            assert_eq!(value.chars().count(), 64);
+            assert!(!value.chars().any(|c| denylist.contains(c)), "{} contains a character of {:?}", value, denylist);
+        }
+    }
+
+    let (status, body) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8, "denylist": "0123456789" })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "denylist removes every character of the charset.");
+
+    let (status, body) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8, "denylist": "012345678" })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["data"].as_str().unwrap().starts_with("Degenerate charset"), "{}", body);
+}
+
+
+#[actix_web::test]
+async fn script_presets_stay_within_their_unicode_ranges() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    for (preset, in_range) in [
+        ("cyrillic", (|c| ('\u{0400}'..='\u{04FF}').contains(&c)) as fn(char) -> bool),
+        ("greek", |c| ('\u{0391}'..='\u{03C9}').contains(&c) && c != '\u{03A2}'),
+        ("hebrew", |c| ('\u{05D0}'..='\u{05EA}').contains(&c)),
+    ] {
+        let mut seen = HashSet::new();
+        for _ in 0..20 {
+            let value = data(&app, post("/generate_random_string", json!({ "charset_preset": preset, "length": 40 })).to_request()).await;
+            let value = value.as_str().unwrap();
+            assert_eq!(value.chars().count(), 40, "length counts characters");
+            assert!(value.chars().all(in_range), "{} has a character outside the {} range", value, preset);
+            seen.extend(value.chars());
+        }
+        assert!(seen.len() > 15, "{} drew only {:?}", preset, seen);
+    }
+
+    let (status, _) = send(&app, post("/generate_random_string", json!({ "charset_preset": "greek", "length": 8, "min_unique_chars": 4 })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn charset_info_matches_what_generation_draws_from() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let info = data(&app, test::TestRequest::get().uri("/charset_info?use_digits=true&denylist=0123").to_request()).await;
+    assert_eq!(info, json!({ "charset": "456789", "size": 6, "excluded": "0123" }));
+
+    for (query, options) in [
+        ("use_digits=true&denylist=0123", json!({ "use_digits": true, "denylist": "0123" })),
+        ("charset_preset=hex&denylist=abc", json!({ "charset_preset": "hex", "denylist": "abc" })),
+        ("use_lowercase=true&use_spec=true&identifier_safe=true", json!({ "use_lowercase": true, "use_spec": true, "identifier_safe": true })),
+        ("charset_preset=greek&denylist=%CE%B1%CE%B2", json!({ "charset_preset": "greek", "denylist": "αβ" })),
+    ] {
+        let info = data(&app, test::TestRequest::get().uri(&format!("/charset_info?{}", query)).to_request()).await;
+        let charset: HashSet<char> = info["charset"].as_str().unwrap().chars().collect();
+        assert_eq!(info["size"], charset.len());
+
+        let mut drawn = HashSet::new();
+        for _ in 0..50 {
+            let mut body = options.clone();
+            body["length"] = json!(64);
+            let value = data(&app, post("/generate_random_string", body).to_request()).await;
+            drawn.extend(value.as_str().unwrap().chars());
+        }
+        // 3200 draws leave a character of a pool this size out with negligible probability
+        assert_eq!(drawn, charset, "{}", query);
+    }
+
+    let (status, _) = send(&app, test::TestRequest::get().uri("/charset_info?charset_preset=nope").to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+
+#[actix_web::test]
+async fn seeded_output_is_pinned_across_versions() {
+    let harness = Harness::new(config(json!({ "seeded_cache_size": 0 })));
+    let app = app!(harness);
+
+    // Changes if the charset order, the ChaCha stream setup or the index sampling changes; all of those break replay
+    let body = json!({ "use_digits": true, "use_spec": true, "length": 16, "seed": 459, "counter": 0 });
+    let value = data(&app, post("/generate_random_string", body).to_request()).await;
+    assert_eq!(value, "1~#!^#>7#&?#&$1!");
+}
+
+
+#[actix_web::test]
+async fn min_unique_chars_holds_or_is_rejected() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    // Ten random digits have nine or more distinct ones only about 1.7% of the time, so this really regenerates
+    for _ in 0..30 {
+        let value = data(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 10, "min_unique_chars": 9 })).to_request()).await;
+        let unique: HashSet<char> = value.as_str().unwrap().chars().collect();
+        assert!(unique.len() >= 9, "{} has {} distinct characters", value, unique.len());
+    }
+
+    for (body, message) in [
+        (json!({ "use_digits": true, "length": 12, "min_unique_chars": 11 }), "min_unique_chars 11 is impossible with length 12 and 10 distinct character(s)"),
+        (json!({ "use_lowercase": true, "length": 4, "min_unique_chars": 5 }), "min_unique_chars 5 is impossible with length 4 and 26 distinct character(s)"),
+    ] {
+        let (status, body) = send(&app, post("/generate_random_string", body).to_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["data"], message);
+    }
+}
+
+
+#[actix_web::test]
+async fn crockford_output_and_check_symbol_validate() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+    let alphabet = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+    let checks = "0123456789ABCDEFGHJKMNPQRSTVWXYZ*~$=U";
+
+    for _ in 0..30 {
+        let value = data(&app, post("/generate_random_string", json!({ "charset_preset": "crockford_base32", "length": 12 })).to_request()).await;
+        let value = value.as_str().unwrap();
+        assert_eq!(value.len(), 12);
+        assert!(value.chars().all(|c| alphabet.contains(c)), "{}", value);
+
+        let checked = data(&app, post("/generate_random_string", json!({ "charset_preset": "crockford_base32", "length": 12, "crockford_check": true })).to_request()).await;
+        let (payload, check) = checked.as_str().unwrap().split_at(12);
+        // 12 symbols are 60 bits, so the number fits a u64 here
+        let number = payload.chars().fold(0u64, |acc, c| acc * 32 + alphabet.find(c).unwrap() as u64);
+        assert_eq!(check, &checks[(number % 37) as usize..][..1], "{}", checked);
+    }
+
+    // 1234 = "16J", and 1234 % 37 = 13
+    assert_eq!(crate::random_module::crockford_check_symbol("16J"), 'D');
+
+    let (status, body) = send(&app, post("/generate_random_string", json!({ "use_digits": true, "length": 8, "crockford_check": true })).to_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["data"], "crockford_check requires charset_preset \"crockford_base32\".");
+}
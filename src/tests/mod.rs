@@ -0,0 +1,202 @@
+// Endpoint tests: the real route table with test app data. Unit tests live next to their module
+use actix_web::{body::MessageBody, dev::{Service, ServiceResponse}, http::StatusCode, test, web, Error};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tracing_subscriber::{filter, fmt, layer::SubscriberExt, reload, EnvFilter};
+
+use crate::cache::LruCache;
+use crate::concurrency::InFlight;
+use crate::metrics::Metrics;
+use crate::trace::{TraceBuffer, TraceMakeWriter};
+use crate::utils::{Config, LogLevelHandle};
+use crate::{json_error_handler, BodyCache, RecentValues, SeededCache, StopSender};
+
+mod choose;
+mod control;
+mod generate;
+mod network;
+mod rpc;
+mod server;
+
+
+
+// Builds the service for a Harness, with every route registered as in main
+macro_rules! app {
+    ($harness:expr) => {
+        actix_web::test::init_service(
+            actix_web::App::new()
+                .configure(|cfg| $harness.register(cfg))
+                .configure(crate::routes),
+        )
+        .await
+    };
+}
+
+pub(crate) use app;
+
+
+
+// The required fields of config.json; `overrides` adds or replaces fields
+pub fn config(overrides: Value) -> Config {
+    let mut fields = json!({
+        "port_manager_ip": "127.0.0.1",
+        "port_manager_port": "1030",
+        "port_manager_endpoint": "getport",
+        "name_for_port_manager": "random_module_test",
+        "logs_dir": "./logs",
+        "workers_count": 1,
+    });
+    if let (Value::Object(fields), Value::Object(overrides)) = (&mut fields, overrides) {
+        fields.extend(overrides);
+    }
+    serde_json::from_value(fields).expect("test config must deserialize")
+}
+
+
+// The app data main registers, kept here so tests can inspect it
+pub struct Harness {
+    pub config: web::Data<Config>,
+    pub in_flight: web::Data<InFlight>,
+    pub recent: web::Data<RecentValues>,
+    pub body_cache: web::Data<BodyCache>,
+    pub seeded_cache: web::Data<SeededCache>,
+    pub metrics: web::Data<Metrics>,
+    pub trace_buffer: web::Data<TraceBuffer>,
+    pub log_level: web::Data<LogLevelHandle>,
+    pub stop: web::Data<StopSender>,
+    // What main's shutdown task would receive
+    pub stop_requests: tokio::sync::mpsc::UnboundedReceiver<()>,
+}
+
+
+impl Harness {
+    pub fn new(config: Config) -> Self {
+        let (stop_tx, stop_requests) = tokio::sync::mpsc::unbounded_channel();
+        // Not installed in any subscriber, so POST /log_level fails with it
+        let (_, log_level) = reload::Layer::new(EnvFilter::new("info"));
+
+        Self {
+            in_flight: web::Data::new(InFlight::default()),
+            recent: web::Data::new(Mutex::new(LruCache::new(config.recent_window.unwrap_or(crate::DEFAULT_RECENT_WINDOW)))),
+            body_cache: web::Data::new(Mutex::new(LruCache::new(config.dedup_cache_size.unwrap_or(crate::DEFAULT_DEDUP_CACHE_SIZE)))),
+            seeded_cache: web::Data::new(Mutex::new(LruCache::new(config.seeded_cache_size.unwrap_or(crate::DEFAULT_SEEDED_CACHE_SIZE)))),
+            metrics: web::Data::new(Metrics::default()),
+            trace_buffer: web::Data::new(TraceBuffer::new(config.trace_lines.unwrap_or(crate::DEFAULT_TRACE_LINES))),
+            log_level: web::Data::new(log_level),
+            stop: web::Data::new(stop_tx),
+            stop_requests,
+            config: web::Data::new(config),
+        }
+    }
+
+    // Sends this thread's events through a reloadable `level` filter into trace_buffer, as init_tracing does, and
+    // hands the filter's handle to POST /log_level. Call before building the app; lasts until the guard is dropped
+    pub fn capture_logs(&mut self, level: &str) -> tracing::subscriber::DefaultGuard {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new(level));
+        self.log_level = web::Data::new(handle);
+
+        let subscriber = tracing_subscriber::registry()
+            .with(filter)
+            .with(filter::dynamic_filter_fn(|meta, _| crate::log_sampling::keep_event(meta)))
+            .with(
+                fmt::layer()
+                    .with_target(true)
+                    .with_writer(TraceMakeWriter(self.trace_buffer.clone().into_inner()))
+                    .with_ansi(false),
+            );
+        tracing::subscriber::set_default(subscriber)
+    }
+
+    pub fn register(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(web::Data::new(Instant::now()))
+            .app_data(self.config.clone())
+            .app_data(self.in_flight.clone())
+            .app_data(self.recent.clone())
+            .app_data(self.body_cache.clone())
+            .app_data(self.seeded_cache.clone())
+            .app_data(self.metrics.clone())
+            .app_data(self.trace_buffer.clone())
+            .app_data(self.log_level.clone())
+            .app_data(self.stop.clone())
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler));
+    }
+}
+
+
+// Registers GET /hold, which stays in flight until the returned sender fires (or is dropped)
+pub fn hold(cfg: &mut web::ServiceConfig) -> oneshot::Sender<()> {
+    let (release, released) = oneshot::channel::<()>();
+    let released = Arc::new(Mutex::new(Some(released)));
+
+    cfg.route("/hold", web::get().to(move || {
+        let released = released.lock().unwrap().take();
+        async move {
+            if let Some(released) = released {
+                let _ = released.await;
+            }
+            "released"
+        }
+    }));
+    release
+}
+
+
+pub fn post(path: &str, body: Value) -> test::TestRequest {
+    test::TestRequest::post().uri(path).set_json(body)
+}
+
+
+// Status and JSON body of one request; an empty or non-JSON body reads as null
+pub async fn send<S, R, B>(app: &S, req: R) -> (StatusCode, Value)
+where
+    S: Service<R, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let resp = test::call_service(app, req).await;
+    let status = resp.status();
+    let body = test::read_body(resp).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+
+// `data` of a successful response, failing the test with the body otherwise
+pub async fn data<S, R, B>(app: &S, req: R) -> Value
+where
+    S: Service<R, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let (status, body) = send(app, req).await;
+    assert_eq!(status, StatusCode::OK, "unexpected response: {}", body);
+    body["data"].clone()
+}
+
+
+// How often each value comes back over `trials` calls; `data` may be one value or an array of picks
+pub async fn tally<S, R, B>(app: &S, request: impl Fn() -> R, trials: usize) -> HashMap<String, usize>
+where
+    S: Service<R, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let mut counts = HashMap::new();
+    for _ in 0..trials {
+        let picks = match data(app, request()).await {
+            Value::Array(picks) => picks,
+            value => vec![value],
+        };
+        for pick in picks {
+            let key = pick.as_str().map(str::to_string).unwrap_or_else(|| pick.to_string());
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+
+// Asserts `count` out of `trials` is within `tolerance` of the expected share
+pub fn assert_share(label: &str, count: usize, trials: usize, expected: f64, tolerance: f64) {
+    let share = count as f64 / trials as f64;
+    assert!((share - expected).abs() <= tolerance, "{}: share {:.3}, expected {:.3} +- {}", label, share, expected, tolerance);
+}
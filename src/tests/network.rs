@@ -0,0 +1,71 @@
+use actix_web::{dev::Service, http::StatusCode, test, web, App, HttpMessage, HttpRequest};
+use std::net::SocketAddr;
+
+use crate::{ClientIp, LocalNetworkOnly};
+
+
+
+// GET /ip answers with the client address LocalNetworkOnly resolved
+fn echo_client_ip(cfg: &mut web::ServiceConfig) {
+    cfg.route("/ip", web::get().to(|req: HttpRequest| async move {
+        req.extensions().get::<ClientIp>().map(|client| client.0.to_string()).unwrap_or_default()
+    }));
+}
+
+
+fn from(peer: &str, forwarded_for: Option<&str>) -> test::TestRequest {
+    let req = test::TestRequest::get().uri("/ip").peer_addr(peer.parse::<SocketAddr>().unwrap());
+    match forwarded_for {
+        Some(ip) => req.insert_header(("X-Forwarded-For", ip)),
+        None => req,
+    }
+}
+
+
+#[actix_web::test]
+async fn forwarding_headers_count_only_from_trusted_proxies() {
+    let app = test::init_service(App::new().wrap(LocalNetworkOnly::new(vec!["203.0.113.7".parse().unwrap(), "10.0.0.1".parse().unwrap()])).configure(echo_client_ip)).await;
+
+    // A public peer claiming to be local is judged by its socket address
+    assert!(app.call(from("198.51.100.9:5000", Some("127.0.0.1")).to_request()).await.is_err());
+
+    // A local peer that is not a trusted proxy cannot pose as another client either
+    let resp = test::call_service(&app, from("192.168.1.30:5000", Some("192.168.1.99")).to_request()).await;
+    assert_eq!(test::read_body(resp).await, "192.168.1.30");
+
+    // Behind a trusted proxy the forwarded client decides, whichever way it goes
+    let resp = test::call_service(&app, from("203.0.113.7:5000", Some("192.168.1.20")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "192.168.1.20");
+    assert!(app.call(from("10.0.0.1:5000", Some("8.8.8.8")).to_request()).await.is_err());
+
+    let resp = test::call_service(&app, from("10.0.0.1:5000", None).to_request()).await;
+    assert_eq!(test::read_body(resp).await, "10.0.0.1");
+}
+
+
+#[actix_web::test]
+async fn forwarded_chain_is_read_from_the_right() {
+    let app = test::init_service(App::new().wrap(LocalNetworkOnly::new(vec!["203.0.113.7".parse().unwrap(), "10.0.0.1".parse().unwrap()])).configure(echo_client_ip)).await;
+
+    // The proxy appended the real client after the address the client made up
+    assert!(app.call(from("203.0.113.7:5000", Some("127.0.0.1, 203.0.113.9")).to_request()).await.is_err());
+
+    // Trusted hops at the right end are skipped down to the first address we don't control
+    let resp = test::call_service(&app, from("10.0.0.1:5000", Some("8.8.8.8, 192.168.1.20, 203.0.113.7")).to_request()).await;
+    assert_eq!(test::read_body(resp).await, "192.168.1.20");
+
+    // Forwarded IPv6 addresses, bare or with a port
+    for forwarded_for in ["::1", "[::1]:4711"] {
+        let resp = test::call_service(&app, from("10.0.0.1:5000", Some(forwarded_for)).to_request()).await;
+        assert_eq!(test::read_body(resp).await, "::1", "{}", forwarded_for);
+    }
+    assert!(app.call(from("10.0.0.1:5000", Some("fe80::1")).to_request()).await.is_err());
+    assert!(app.call(from("10.0.0.1:5000", Some("not an address")).to_request()).await.is_err());
+
+    // The same rules for the standard Forwarded header
+    let forwarded = |value: &'static str| from("10.0.0.1:5000", None).insert_header(("Forwarded", value)).to_request();
+    let resp = test::call_service(&app, forwarded("for=\"[::1]:4711\";proto=http")).await;
+    assert_eq!(test::read_body(resp).await, "::1");
+    assert!(app.call(forwarded("for=127.0.0.1, for=203.0.113.9")).await.is_err());
+}
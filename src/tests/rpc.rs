@@ -0,0 +1,84 @@
+use actix_web::http::StatusCode;
+use serde_json::json;
+
+use super::{app, config, post, send, Harness};
+
+
+
+#[actix_web::test]
+async fn rpc_call_returns_result() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let call = json!({ "jsonrpc": "2.0", "id": 7, "method": "generate_random_string", "params": { "use_digits": true, "length": 8 } });
+    let (status, body) = send(&app, post("/rpc", call).to_request()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["jsonrpc"], "2.0");
+    assert_eq!(body["id"], 7);
+    assert!(body.get("error").is_none());
+    let value = body["result"].as_str().unwrap();
+    assert!(value.len() == 8 && value.bytes().all(|b| b.is_ascii_digit()), "{}", value);
+}
+
+
+#[actix_web::test]
+async fn rpc_invalid_params_is_an_error_object() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    // Missing `length` fails deserialization; a zero count fails validation. Both are invalid params
+    for params in [json!({ "use_digits": true }), json!({ "items": [1, 2], "count": 0 })] {
+        let method = if params.get("items").is_some() { "generate_random_choose" } else { "generate_random_string" };
+        let call = json!({ "jsonrpc": "2.0", "id": "a", "method": method, "params": params });
+        let (status, body) = send(&app, post("/rpc", call).to_request()).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], "a");
+        assert!(body.get("result").is_none());
+        assert_eq!(body["error"]["code"], -32602, "{}", body);
+    }
+}
+
+
+#[actix_web::test]
+async fn rpc_batch_answers_each_call_except_notifications() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let batch = json!([
+        { "jsonrpc": "2.0", "id": 1, "method": "generate_random_choose", "params": { "items": ["a", "b", "c"], "count": 2 } },
+        { "jsonrpc": "2.0", "id": 2, "method": "no_such_method" },
+        { "jsonrpc": "2.0", "method": "generate_random_string", "params": { "use_digits": true, "length": 4 } },
+        { "id": 3, "method": "generate_random_string" },
+    ]);
+    let (status, body) = send(&app, post("/rpc", batch).to_request()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let responses = body.as_array().unwrap();
+    assert_eq!(responses.len(), 3, "the notification gets no response: {}", body);
+
+    let picks = responses[0]["result"].as_array().unwrap();
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(picks.len(), 2);
+    assert!(picks.iter().all(|pick| ["a", "b", "c"].contains(&pick.as_str().unwrap())));
+    assert_eq!(responses[1]["id"], 2);
+    assert_eq!(responses[1]["error"]["code"], -32601);
+    assert_eq!(responses[2]["id"], 3);
+    assert_eq!(responses[2]["error"]["code"], -32600);
+}
+
+
+#[actix_web::test]
+async fn rpc_parse_error_and_empty_batch() {
+    let harness = Harness::new(config(json!({})));
+    let app = app!(harness);
+
+    let req = actix_web::test::TestRequest::post().uri("/rpc").set_payload("{not json").to_request();
+    let (_, body) = send(&app, req).await;
+    assert_eq!(body["error"]["code"], -32700);
+    assert_eq!(body["id"], serde_json::Value::Null);
+
+    let (_, body) = send(&app, post("/rpc", json!([])).to_request()).await;
+    assert_eq!(body["error"]["code"], -32600);
+}
@@ -0,0 +1,317 @@
+// Tests that need a real listening server rather than the in-process test service
+use actix_web::{web, App, HttpServer};
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{config, Harness};
+
+
+
+// Listens on an ephemeral local port with the harness app data, every route, and GET /sleep?ms=N
+fn serve(harness: Harness, shutdown_timeout: u64) -> (actix_web::dev::Server, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let harness = Arc::new(harness);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .configure(|cfg| harness.register(cfg))
+            .route("/sleep", web::get().to(|query: web::Query<std::collections::HashMap<String, u64>>| async move {
+                tokio::time::sleep(Duration::from_millis(query.get("ms").copied().unwrap_or(0))).await;
+                "slept"
+            }))
+            .configure(crate::routes)
+    })
+    .workers(1)
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    (server, base)
+}
+
+
+// Just enough of a WebSocket client for /ws/generate: unfragmented text frames, masked as clients must
+struct WsClient {
+    stream: BufReader<TcpStream>,
+}
+
+
+impl WsClient {
+    async fn connect(base: &str, path: &str) -> Self {
+        let mut stream = BufReader::new(TcpStream::connect(base.trim_start_matches("http://")).await.unwrap());
+        let upgrade = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path
+        );
+        stream.get_mut().write_all(upgrade.as_bytes()).await.unwrap();
+
+        let mut status = String::new();
+        stream.read_line(&mut status).await.unwrap();
+        assert!(status.starts_with("HTTP/1.1 101"), "upgrade refused: {}", status);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+        }
+        Self { stream }
+    }
+
+    async fn send(&mut self, text: &str) {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x81];
+        match text.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(text.bytes().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        self.stream.get_mut().write_all(&frame).await.unwrap();
+    }
+
+    // The next text frame as JSON, skipping pings
+    async fn recv(&mut self) -> Value {
+        loop {
+            let mut head = [0u8; 2];
+            self.stream.read_exact(&mut head).await.unwrap();
+            let len = match head[1] & 0x7f {
+                126 => self.stream.read_u16().await.unwrap() as usize,
+                127 => self.stream.read_u64().await.unwrap() as usize,
+                len => len as usize,
+            };
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload).await.unwrap();
+
+            match head[0] & 0x0f {
+                0x1 => return serde_json::from_slice(&payload).unwrap(),
+                0x9 => continue,
+                opcode => panic!("unexpected frame opcode {:#x}", opcode),
+            }
+        }
+    }
+}
+
+
+#[actix_web::test]
+async fn ws_generate_streams_batches_progress_and_completion() {
+    let (server, base) = serve(Harness::new(config(json!({}))), 1);
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    let mut ws = WsClient::connect(&base, "/ws/generate").await;
+
+    ws.send(&json!({ "total": 2500, "batch_size": 1000, "use_digits": true, "length": 8 }).to_string()).await;
+    let mut done = 0;
+    for expected in [1000, 1000, 500] {
+        let batch = ws.recv().await;
+        assert_eq!(batch["type"], "batch");
+        let values = batch["values"].as_array().unwrap();
+        assert_eq!(values.len(), expected);
+        assert!(values.iter().all(|value| value.as_str().is_some_and(|value| value.len() == 8 && value.bytes().all(|b| b.is_ascii_digit()))));
+
+        done += expected;
+        assert_eq!(ws.recv().await, json!({ "type": "progress", "done": done, "total": 2500 }));
+    }
+    assert_eq!(ws.recv().await, json!({ "type": "complete", "total": 2500 }));
+
+    // The socket stays open for the next job, and a bad spec is reported rather than closing it
+    ws.send(&json!({ "total": 0, "use_digits": true, "length": 8 }).to_string()).await;
+    let error = ws.recv().await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["message"], "total must be 1–1000000 and batch_size 1–10000");
+
+    ws.send(&json!({ "total": 3, "use_digits": true, "length": 8 }).to_string()).await;
+    assert_eq!(ws.recv().await["type"], "batch");
+    assert_eq!(ws.recv().await, json!({ "type": "progress", "done": 3, "total": 3 }));
+    assert_eq!(ws.recv().await, json!({ "type": "complete", "total": 3 }));
+
+    handle.stop(false).await;
+}
+
+
+#[actix_web::test]
+async fn ws_time_budget_ends_the_job_early_but_cleanly() {
+    let (server, base) = serve(Harness::new(config(json!({}))), 1);
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    let mut ws = WsClient::connect(&base, "/ws/generate").await;
+
+    ws.send(&json!({ "total": 1_000_000, "batch_size": 10_000, "time_budget_ms": 20, "use_lowercase": true, "length": 32 }).to_string()).await;
+    let mut received = 0;
+    let complete = loop {
+        let frame = ws.recv().await;
+        match frame["type"].as_str().unwrap() {
+            "batch" => received += frame["values"].as_array().unwrap().len(),
+            "progress" => assert_eq!(frame["done"], received),
+            _ => break frame,
+        }
+    };
+
+    assert_eq!(complete["type"], "complete");
+    assert_eq!(complete["budget_exhausted"], true);
+    assert_eq!(complete["total"], received);
+    assert!(received < 1_000_000, "the budget should cut the job short");
+
+    // The socket is still usable after a cut-short job
+    ws.send(&json!({ "total": 2, "use_lowercase": true, "length": 4 }).to_string()).await;
+    assert_eq!(ws.recv().await["type"], "batch");
+    assert_eq!(ws.recv().await["type"], "progress");
+    assert_eq!(ws.recv().await, json!({ "type": "complete", "total": 2 }));
+
+    handle.stop(false).await;
+}
+
+
+#[actix_web::test]
+async fn ws_seeded_counter_near_the_top_is_rejected_instead_of_overflowing() {
+    let (server, base) = serve(Harness::new(config(json!({}))), 1);
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    let mut ws = WsClient::connect(&base, "/ws/generate").await;
+
+    // Counters u64::MAX - 1, u64::MAX and one past the end
+    ws.send(&json!({ "total": 3, "seed": 438, "counter": u64::MAX - 1, "use_digits": true, "length": 8 }).to_string()).await;
+    let error = ws.recv().await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["message"], format!("counter {} leaves no room for 3 values before the counter overflows", u64::MAX - 1));
+
+    // Exactly enough room still works, on the same socket
+    ws.send(&json!({ "total": 2, "seed": 438, "counter": u64::MAX - 1, "use_digits": true, "length": 8 }).to_string()).await;
+    assert_eq!(ws.recv().await["values"].as_array().unwrap().len(), 2);
+    assert_eq!(ws.recv().await["type"], "progress");
+    assert_eq!(ws.recv().await, json!({ "type": "complete", "total": 2 }));
+
+    handle.stop(false).await;
+}
+
+
+#[actix_web::test]
+async fn a_middleware_panic_does_not_stop_the_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let harness = Arc::new(Harness::new(config(json!({}))));
+
+    // Panics outside any handler's catch_unwind, on the only worker
+    let server = HttpServer::new(move || {
+        App::new()
+            .configure(|cfg| harness.register(cfg))
+            .wrap_fn(|req, srv| {
+                use actix_web::dev::Service;
+                assert!(req.path() != "/boom", "middleware panic");
+                srv.call(req)
+            })
+            .configure(crate::routes)
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    let client = reqwest::Client::new();
+
+    for _ in 0..3 {
+        assert!(client.get(format!("{}/boom", base)).send().await.is_err(), "the panicking request gets no response");
+        let resp = client.get(format!("{}/healthz", base)).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    handle.stop(false).await;
+}
+
+
+#[actix_web::test]
+async fn every_bind_address_accepts_requests() {
+    let free_port = || TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = config(json!({ "bind_addresses": [format!("127.0.0.1:{}", free_port()), format!(" 127.0.0.1:{} ", free_port())] }));
+    let addresses = config.bind_addresses().unwrap();
+    assert_eq!(addresses.len(), 2);
+
+    let harness = Arc::new(Harness::new(config));
+    let mut server = HttpServer::new(move || App::new().configure(|cfg| harness.register(cfg)).configure(crate::routes))
+        .workers(1)
+        .disable_signals();
+    for address in &addresses {
+        server = server.bind((address.ip().to_string(), address.port())).unwrap();
+    }
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    for address in &addresses {
+        let resp = reqwest::get(format!("http://{}/healthz", address)).await.unwrap();
+        assert_eq!(resp.status(), 200, "{}", address);
+    }
+    handle.stop(false).await;
+
+    let err = super::config(json!({ "bind_addresses": ["127.0.0.1:8080", "localhost:8080"] })).validate().unwrap_err();
+    assert_eq!(err, "bind_addresses: \"localhost:8080\" is not a valid address (expected IP:PORT)");
+}
+
+
+#[actix_web::test]
+async fn stop_drains_short_requests_and_cuts_off_long_ones() {
+    let mut harness = Harness::new(config(json!({ "allow_remote_stop": true })));
+    let stop_requests = std::mem::replace(&mut harness.stop_requests, tokio::sync::mpsc::unbounded_channel().1);
+    let (server, base) = serve(harness, 1);
+    crate::stop_on_request(server.handle(), stop_requests);
+    let server = actix_web::rt::spawn(server);
+    let client = reqwest::Client::new();
+
+    let short = actix_web::rt::spawn(client.get(format!("{}/sleep?ms=300", base)).send());
+    let long = actix_web::rt::spawn(client.get(format!("{}/sleep?ms=10000", base)).send());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let stop = client.get(format!("{}/stop", base)).send().await.unwrap();
+    assert_eq!(stop.status(), 200);
+    let stopped_at = Instant::now();
+
+    // Within shutdown_timeout_secs: finishes normally
+    let short = short.await.unwrap().unwrap();
+    assert_eq!(short.text().await.unwrap(), "slept");
+
+    // Past it: the connection is dropped instead of waiting out the 10 s
+    assert!(long.await.unwrap().is_err(), "the long request should have been cut off");
+    server.await.unwrap().unwrap();
+    assert!(stopped_at.elapsed() < Duration::from_secs(5), "shutdown took {:?}", stopped_at.elapsed());
+
+    assert!(client.get(format!("{}/healthz", base)).send().await.is_err(), "a stopped server accepts no connections");
+}
+
+
+#[test]
+fn each_worker_thread_is_pinned_once() {
+    // Pinning is unsupported on this platform; startup rejects `affinity` there
+    let Some(cores) = core_affinity::get_core_ids() else { return };
+    let affinity = vec![cores[0]];
+    let next_worker = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    for _ in 0..2 {
+        let (affinity, next_worker) = (affinity.clone(), Arc::clone(&next_worker));
+        std::thread::spawn(move || {
+            // With two listeners the app factory runs twice on every worker thread
+            crate::pin_worker(&affinity, &next_worker);
+            crate::pin_worker(&affinity, &next_worker);
+
+            #[cfg(target_os = "linux")]
+            {
+                let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+                let allowed = status.lines().find_map(|line| line.strip_prefix("Cpus_allowed_list:")).unwrap();
+                assert_eq!(allowed.trim(), affinity[0].id.to_string());
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    assert_eq!(next_worker.load(std::sync::atomic::Ordering::SeqCst), 2, "one slot per thread, not per factory run");
+}
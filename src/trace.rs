@@ -0,0 +1,102 @@
+use actix_web::{get, web, HttpResponse};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::success;
+use crate::utils::Config;
+
+
+
+// Keeps the last `capacity` formatted log lines in memory for GET /trace
+pub struct TraceBuffer {
+    capacity: usize,
+    lines: Mutex<VecDeque<String>>,
+}
+
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn push(&self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
+
+#[derive(Clone)]
+pub struct TraceMakeWriter(pub Arc<TraceBuffer>);
+
+
+impl<'a> MakeWriter<'a> for TraceMakeWriter {
+    type Writer = TraceWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        TraceWriter { buffer: Arc::clone(&self.0), bytes: Vec::new() }
+    }
+}
+
+
+// One writer per event; the finished line is stored when the writer is dropped
+pub struct TraceWriter {
+    buffer: Arc<TraceBuffer>,
+    bytes: Vec<u8>,
+}
+
+
+impl io::Write for TraceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.bytes);
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            self.buffer.push(line.to_string());
+        }
+    }
+}
+
+
+
+#[get("/trace")]
+pub async fn trace_handler(buffer: web::Data<TraceBuffer>, config: web::Data<Config>) -> HttpResponse {
+    if let Some(forbidden) = crate::admin_disabled(&config, "trace") {
+        return forbidden;
+    }
+
+    let secrets = config.secret_values();
+    let lines: Vec<String> = buffer
+        .snapshot()
+        .into_iter()
+        .map(|line| secrets.iter().fold(line, |line, secret| line.replace(secret.as_str(), "***")))
+        .collect();
+
+    success(lines)
+}
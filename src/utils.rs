@@ -1,23 +1,212 @@
 use chrono::Local;
-use serde::Deserialize;
-use std::fs;
-use tracing::{error, info, warn};
-use tracing_subscriber;
-use reqwest;
+use serde::{Deserialize, Serialize};
+use std::{fs, io};
+use tracing::{error, info, warn, Subscriber};
+use tracing_subscriber::{filter, fmt, prelude::*, registry::LookupSpan, reload, EnvFilter, Layer, Registry};
 use tokio::time::{sleep, Duration};
-use serde_json::json;
-use std::net::{UdpSocket, IpAddr};
+use serde_json::{json, Value};
+use std::net::{UdpSocket, IpAddr, SocketAddr};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use once_cell::sync::OnceCell;
 
+use crate::breaker::CircuitBreaker;
+use crate::log_sampling;
+use crate::random_module::SeedSource;
+#[cfg(unix)]
+use crate::syslog::SyslogMakeWriter;
+use crate::trace::{TraceBuffer, TraceMakeWriter};
 
 
-#[derive(Deserialize, Clone)]
+
+const DEFAULT_PORT_BREAKER_THRESHOLD: u32 = 3;
+const DEFAULT_PORT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+
+// Config fields holding secrets; /config replaces their values with "***"
+const SECRET_FIELDS: &[&str] = &["pepper", "signing_key"];
+
+
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub port_manager_ip: String,
     pub port_manager_port: String,
     pub port_manager_endpoint: String,
     pub name_for_port_manager: String,
     pub logs_dir: String,
-    pub workers_count: usize
+    pub workers_count: usize,
+    pub port_fetch_fail_fast: Option<bool>,
+    pub min_length: Option<usize>,
+    pub max_concurrent_requests: Option<usize>,
+    pub allow_remote_stop: Option<bool>,
+    pub log_level: Option<String>,
+    pub max_json_depth: Option<usize>,
+    pub max_body_bytes: Option<usize>,
+    pub max_stream_bytes: Option<usize>,
+    pub recent_window: Option<usize>,
+    pub chaos_error_rate: Option<f64>,
+    pub min_response_ms: Option<u64>,
+    pub trace_lines: Option<usize>,
+    pub seed_source: Option<String>,
+    pub dedup_ttl_secs: Option<u64>,
+    pub dedup_cache_size: Option<usize>,
+    pub shutdown_timeout_secs: Option<u64>,
+    pub trusted_proxies: Option<Vec<String>>,
+    pub allow_admin_endpoints: Option<bool>,
+    pub port_breaker_threshold: Option<u32>,
+    pub port_breaker_cooldown_secs: Option<u64>,
+    pub affinity: Option<Vec<usize>>,
+    pub min_charset_size: Option<usize>,
+    pub slow_request_threshold_ms: Option<u64>,
+    pub retry_on_panic: Option<bool>,
+    pub route_prefix: Option<String>,
+    pub probes_at_root: Option<bool>,
+    pub max_items: Option<usize>,
+    pub legacy_error_format: Option<bool>,
+    pub entropy_file: Option<String>,
+    pub seeded_cache_size: Option<usize>,
+    pub bind_addresses: Option<Vec<String>>,
+    pub max_concurrent_per_ip: Option<usize>,
+    pub log_target: Option<String>,
+    pub entropy_retries: Option<u32>,
+    pub pepper: Option<String>,
+    pub signing_key: Option<String>,
+    pub max_connections: Option<usize>,
+    pub log_sample_rate: Option<f64>,
+    pub clamp_count: Option<bool>,
+}
+
+
+impl Config {
+    // Port manager address, checked field by field so a bad config names the culprit
+    pub fn port_manager_url(&self) -> Result<reqwest::Url, String> {
+        let ip: IpAddr = self.port_manager_ip.trim().parse()
+            .map_err(|_| format!("port_manager_ip: {:?} is not a valid IP address", self.port_manager_ip))?;
+        let port: u16 = self.port_manager_port.trim().parse()
+            .map_err(|_| format!("port_manager_port: {:?} is not a valid port number", self.port_manager_port))?;
+
+        let host = match ip {
+            IpAddr::V4(v4) => v4.to_string(),
+            IpAddr::V6(v6) => format!("[{}]", v6),
+        };
+
+        reqwest::Url::parse(&format!("http://{}:{}/{}", host, port, self.port_manager_endpoint.trim_start_matches('/')))
+            .map_err(|e| format!("port_manager_endpoint: {:?} does not form a valid URL ({})", self.port_manager_endpoint, e))
+    }
+
+    // Every check load_config makes before startup; the error names the offending field
+    pub fn validate(&self) -> Result<(), String> {
+        self.port_manager_url().map(|_| ())
+            .and(self.seed_source().map(|_| ()))
+            .and(self.log_to_syslog().map(|_| ()))
+            .and(self.trusted_proxies().map(|_| ()))
+            .and(self.bind_addresses().map(|_| ()))
+            .and(match self.log_sample_rate {
+                Some(rate) if !(0.0..=1.0).contains(&rate) => Err(format!("log_sample_rate: {} is not between 0.0 and 1.0", rate)),
+                _ => Ok(()),
+            })
+            .and(match self.max_connections {
+                Some(0) => Err("max_connections: must be at least 1".to_string()),
+                _ => Ok(()),
+            })
+            .and(self.signing_key.as_deref().map_or(Ok(()), |key| crate::signing::parse_signing_key(key).map(|_| ())))
+    }
+
+    pub fn redacted(&self) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+        if let Value::Object(fields) = &mut value {
+            for (name, field) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&name.as_str()) && !field.is_null() {
+                    *field = Value::from("***");
+                }
+            }
+        }
+        value
+    }
+
+    pub fn seed_source(&self) -> Result<SeedSource, String> {
+        match &self.seed_source {
+            Some(value) => value.parse().map_err(|e| format!("seed_source: {}", e)),
+            None => Ok(SeedSource::Os),
+        }
+    }
+
+    // true for "syslog", false for "file" (the default)
+    pub fn log_to_syslog(&self) -> Result<bool, String> {
+        match self.log_target.as_deref().map(str::trim) {
+            None | Some("file") => Ok(false),
+            Some("syslog") => Ok(true),
+            Some(other) => Err(format!("log_target: {:?} is not a log target (expected \"file\" or \"syslog\")", other)),
+        }
+    }
+
+    // actix applies max_connections per worker, so the configured total is split between them, rounding up
+    pub fn max_connections_per_worker(&self) -> Option<usize> {
+        self.max_connections.map(|total| total.div_ceil(self.workers_count.max(1)))
+    }
+
+    pub fn trusted_proxies(&self) -> Result<Vec<IpAddr>, String> {
+        self.trusted_proxies
+            .iter()
+            .flatten()
+            .map(|proxy| proxy.trim().parse().map_err(|_| format!("trusted_proxies: {:?} is not a valid IP address", proxy)))
+            .collect()
+    }
+
+    pub fn bind_addresses(&self) -> Result<Vec<SocketAddr>, String> {
+        self.bind_addresses
+            .iter()
+            .flatten()
+            .map(|address| address.trim().parse().map_err(|_| format!("bind_addresses: {:?} is not a valid address (expected IP:PORT)", address)))
+            .collect()
+    }
+
+    // Cores listed in `affinity`, checked against the cores this machine actually has
+    pub fn affinity_cores(&self) -> Result<Vec<core_affinity::CoreId>, String> {
+        let Some(requested) = &self.affinity else {
+            return Ok(Vec::new());
+        };
+
+        let available = core_affinity::get_core_ids().ok_or("affinity: CPU pinning is not supported on this platform")?;
+        requested
+            .iter()
+            .map(|&id| {
+                available
+                    .iter()
+                    .copied()
+                    .find(|core| core.id == id)
+                    .ok_or_else(|| format!("affinity: core {} does not exist (available: 0–{})", id, available.len().saturating_sub(1)))
+            })
+            .collect()
+    }
+
+    // "random/" and "/random" both become "/random"; no prefix is ""
+    pub fn route_prefix(&self) -> String {
+        let trimmed = self.route_prefix.as_deref().unwrap_or_default().trim().trim_matches('/');
+        if trimmed.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", trimmed)
+        }
+    }
+
+    // Values of the secret fields, so log output can be scrubbed the same way /config is
+    pub fn secret_values(&self) -> Vec<String> {
+        let Ok(Value::Object(fields)) = serde_json::to_value(self) else {
+            return Vec::new();
+        };
+        fields
+            .into_iter()
+            .filter(|(name, _)| SECRET_FIELDS.contains(&name.as_str()))
+            .filter_map(|(_, field)| match field {
+                Value::Null => None,
+                Value::String(s) if s.is_empty() => None,
+                Value::String(s) => Some(s),
+                other => Some(other.to_string()),
+            })
+            .collect()
+    }
 }
 
 
@@ -40,44 +229,167 @@ pub fn get_local_ip() -> Option<IpAddr> {
 pub fn load_config() -> Config {
     let config_path = "config.json";
     let config_data = fs::read_to_string(config_path).expect("Can't read config.json");
-    serde_json::from_str(&config_data).expect("Can't parse config.json")
+    let config: Config = serde_json::from_str(&config_data).expect("Can't parse config.json");
+
+    if let Err(e) = config.validate() {
+        eprintln!("Invalid config.json: {}", e);
+        std::process::exit(1);
+    }
+
+    config
 }
 
 
-pub fn init_tracing(logs_dir: &str, log_name: &str) {
-    let date = Local::now().format("%d_%m_%Y").to_string();
-    let log_dir = if logs_dir.trim().is_empty() {
-        "./logs"
-    } else {
-        logs_dir
-    };
+pub type LogLevelHandle = reload::Handle<EnvFilter, Registry>;
+
 
-    fs::create_dir_all(log_dir).expect("Can't create logs directory");
+// Logs every panic with its location, including ones outside a handler's catch_unwind (middleware,
+// extractors). actix replaces a worker thread that dies this way, so the worker count stays at workers_count
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("non-string payload");
+        let location = info.location().map(|location| location.to_string()).unwrap_or_else(|| "unknown location".into());
+        // The thread name comes with every log line already
+        error!(target: "panic", "Panicked at {}: {}", location, message);
+        default_hook(info);
+    }));
+}
 
-    let log_path = format!("{}/{}_{}.log", log_dir, log_name, date);
 
-    tracing_subscriber::fmt()
+// syslog stamps its own time
+#[cfg(unix)]
+fn syslog_layer<S>(log_name: &str) -> io::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let writer = SyslogMakeWriter::connect(log_name)?;
+    Ok(fmt::layer()
         .with_target(true)
-        .with_writer(
-            fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&log_path)
-                .expect("Can't open log file"),
-        )
+        .with_writer(writer)
         .with_thread_names(true)
         .with_ansi(false)
+        .without_time()
+        .boxed())
+}
+
+
+// There is no /dev/log to send to, so the log_target falls back like an unreachable daemon
+#[cfg(not(unix))]
+fn syslog_layer<S>(_log_name: &str) -> io::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Err(io::Error::new(io::ErrorKind::Unsupported, "syslog is only supported on Unix"))
+}
+
+
+pub fn init_tracing(logs_dir: &str, log_name: &str, log_level: Option<&str>, use_syslog: bool, trace_buffer: Arc<TraceBuffer>) -> LogLevelHandle {
+    // Without a reachable syslog daemon (or off Unix) the log file is used instead, so nothing is lost
+    let (syslog, syslog_error) = match use_syslog.then(|| syslog_layer(log_name)) {
+        Some(Ok(layer)) => (Some(layer), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    let log_file = syslog.is_none().then(|| {
+        let date = Local::now().format("%d_%m_%Y").to_string();
+        let log_dir = if logs_dir.trim().is_empty() {
+            "./logs"
+        } else {
+            logs_dir
+        };
+
+        fs::create_dir_all(log_dir).expect("Can't create logs directory");
+
+        let log_path = format!("{}/{}_{}.log", log_dir, log_name, date);
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .expect("Can't open log file")
+    });
+
+    let requested_level = log_level.unwrap_or("info");
+    let (filter, invalid_level) = match EnvFilter::try_new(requested_level) {
+        Ok(filter) => (filter, false),
+        Err(_) => (EnvFilter::new("info"), true),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        // Dynamic, since the answer changes per request and must not be cached per callsite
+        .with(filter::dynamic_filter_fn(|meta, _| log_sampling::keep_event(meta)))
+        .with(log_file.map(|file| {
+            fmt::layer()
+                .with_target(true)
+                .with_writer(file)
+                .with_thread_names(true)
+                .with_ansi(false)
+        }))
+        .with(syslog)
+        .with(
+            fmt::layer()
+                .with_target(true)
+                .with_writer(TraceMakeWriter(trace_buffer))
+                .with_thread_names(true)
+                .with_ansi(false),
+        )
         .init();
+
+    if let Some(e) = syslog_error {
+        warn!(target: "main", "Can't reach syslog ({}), logging to the log file instead", e);
+    }
+
+    if invalid_level {
+        warn!(target: "main", "Invalid log_level {:?} in config, falling back to info", requested_level);
+    }
+
+    handle
+}
+
+
+// One breaker per port manager URL, shared by every fetch_port call, so repeated registrations back off
+// from a failing port manager
+static PORT_MANAGER_BREAKERS: OnceCell<Mutex<HashMap<String, CircuitBreaker>>> = OnceCell::new();
+
+
+fn with_port_manager_breaker<R>(config: &Config, url: &reqwest::Url, f: impl FnOnce(&mut CircuitBreaker) -> R) -> R {
+    let mut breakers = PORT_MANAGER_BREAKERS.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let breaker = breakers.entry(url.to_string()).or_insert_with(|| {
+        CircuitBreaker::new(
+            config.port_breaker_threshold.unwrap_or(DEFAULT_PORT_BREAKER_THRESHOLD),
+            Duration::from_secs(config.port_breaker_cooldown_secs.unwrap_or(DEFAULT_PORT_BREAKER_COOLDOWN_SECS)),
+        )
+    });
+    f(breaker)
+}
+
+
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    resp.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
 }
 
 
 pub async fn fetch_port(config: &Config) -> Option<u16> {
-    let url = format!(
-        "http://{}:{}/{}",
-        config.port_manager_ip,
-        config.port_manager_port,
-        config.port_manager_endpoint
-    );
+    let url = match config.port_manager_url() {
+        Ok(url) => url,
+        Err(e) => {
+            error!(target: "port_resolver", "Invalid port manager address: {}", e);
+            return None;
+        }
+    };
 
     let local_ip = get_local_ip().unwrap_or_else(|| {
         error!(target: "port_resolver", "Failed to determine local IP, using 127.0.0.1 as fallback");
@@ -89,11 +401,20 @@ pub async fn fetch_port(config: &Config) -> Option<u16> {
         "service_name": config.name_for_port_manager
     });
 
-    for attempt in 1..=3 {
+    let attempts = if config.port_fetch_fail_fast.unwrap_or(false) { 1 } else { 3 };
+
+    for attempt in 1..=attempts {
+        if !with_port_manager_breaker(config, &url, CircuitBreaker::allow) {
+            error!(target: "port_resolver", "Circuit breaker open, not contacting the port manager at {}", url);
+            return None;
+        }
+
         info!(target: "port_resolver", "Attempt {}: Requesting port from {} with body {:?}", attempt, url, body);
 
+        let mut retry_delay = None;
+
         match reqwest::Client::new()
-            .post(&url)
+            .post(url.clone())
             .json(&body)
             .send()
             .await
@@ -106,6 +427,7 @@ pub async fn fetch_port(config: &Config) -> Option<u16> {
                                 if let Some(port_val) = json.data.as_u64() {
                                     let port = port_val as u16;
                                     info!(target: "port_resolver", "Received port: {}", port);
+                                    with_port_manager_breaker(config, &url, CircuitBreaker::record_success);
                                     return Some(port);
                                 } else {
                                     error!(target: "port_resolver", "No port found in response data");
@@ -117,20 +439,150 @@ pub async fn fetch_port(config: &Config) -> Option<u16> {
                         Err(e) => error!(target: "port_resolver", "JSON parse error: {}", e),
                     }
                 } else {
-                    warn!(target: "port_resolver", "Response status: {}", resp.status());
-                }
-            }
-            Err(e) => {
-                warn!(target: "port_resolver", "Attempt {} failed: {}", attempt, e);
-                if attempt == 3 {
-                    error!(target: "port_resolver", "All attempts to fetch port failed");
-                    return None;
+                    retry_delay = retry_after(&resp);
+                    warn!(target: "port_resolver", "Response status: {} (Retry-After: {:?})", resp.status(), retry_delay);
                 }
             }
+            Err(e) => warn!(target: "port_resolver", "Attempt {} failed: {}", attempt, e),
+        }
+
+        with_port_manager_breaker(config, &url, |breaker| breaker.record_failure(retry_delay));
+
+        if attempt == attempts {
+            error!(target: "port_resolver", "All attempts to fetch port failed");
+            return None;
         }
 
-        sleep(Duration::from_secs(1)).await;
+        sleep(retry_delay.unwrap_or(Duration::from_secs(1))).await;
     }
 
     None
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::tests::config;
+
+
+    #[test]
+    fn max_connections_is_split_between_workers() {
+        let per_worker = |max_connections: usize, workers_count: usize| {
+            config(json!({ "max_connections": max_connections, "workers_count": workers_count })).max_connections_per_worker()
+        };
+
+        assert_eq!(per_worker(100, 4), Some(25));
+        assert_eq!(per_worker(10, 4), Some(3));
+        assert_eq!(per_worker(3, 8), Some(1), "every worker accepts at least one connection");
+        assert_eq!(per_worker(100, 1), Some(100));
+        assert_eq!(config(json!({ "workers_count": 4 })).max_connections_per_worker(), None);
+    }
+
+
+    #[test]
+    fn syslog_target_falls_back_without_a_daemon() {
+        match syslog_layer::<Registry>("random_module_test") {
+            // Off Unix there is never a syslog layer
+            Err(e) if cfg!(not(unix)) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+            Err(_) => {}
+            Ok(_) => assert!(cfg!(unix) && std::path::Path::new("/dev/log").exists(), "connected to syslog without /dev/log"),
+        }
+    }
+
+
+    // Port manager stand-in answering every request with `response`; returns its port and a request counter
+    async fn mock_port_manager(response: &'static str) -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (port, requests)
+    }
+
+
+    #[tokio::test]
+    async fn fail_fast_makes_a_single_attempt_without_sleeping() {
+        let (port, requests) = mock_port_manager("HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").await;
+        let config = config(json!({ "port_manager_port": port.to_string(), "port_fetch_fail_fast": true }));
+
+        let started = Instant::now();
+        assert_eq!(fetch_port(&config).await, None);
+
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(started.elapsed() < Duration::from_millis(900), "took {:?}", started.elapsed());
+    }
+
+
+    #[tokio::test]
+    async fn retry_after_replaces_the_pause_between_attempts() {
+        let (port, requests) = mock_port_manager("HTTP/1.1 503 Service Unavailable\r\nretry-after: 0\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").await;
+        let config = config(json!({ "port_manager_port": port.to_string() }));
+
+        let started = Instant::now();
+        assert_eq!(fetch_port(&config).await, None);
+
+        // Three attempts, without the default 1 s pauses between them
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(started.elapsed() < Duration::from_millis(900), "took {:?}", started.elapsed());
+    }
+
+
+    #[tokio::test]
+    async fn breaker_stops_requests_until_the_cooldown_then_sends_one_trial() {
+        let (port, requests) = mock_port_manager("HTTP/1.1 503 Service Unavailable\r\nretry-after: 0\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").await;
+        let config = config(json!({ "port_manager_port": port.to_string(), "port_breaker_threshold": 2, "port_breaker_cooldown_secs": 1 }));
+
+        // The second failure opens the breaker, so the third attempt is never sent
+        assert_eq!(fetch_port(&config).await, None);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Later registrations are refused without contacting the port manager
+        assert_eq!(fetch_port(&config).await, None);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // After the cooldown a single trial goes out; its failure opens the breaker again at once
+        sleep(Duration::from_millis(1100)).await;
+        assert_eq!(fetch_port(&config).await, None);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+
+    #[tokio::test]
+    async fn port_is_read_from_the_response() {
+        let (port, requests) = mock_port_manager("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 31\r\nconnection: close\r\n\r\n{\"success\": true, \"data\": 4242}").await;
+        let config = config(json!({ "port_manager_port": port.to_string(), "port_fetch_fail_fast": true }));
+
+        assert_eq!(fetch_port(&config).await, Some(4242));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+
+    #[test]
+    fn malformed_port_manager_address_names_the_field() {
+        let err = config(json!({ "port_manager_ip": "300.1.1.1" })).validate().unwrap_err();
+        assert_eq!(err, "port_manager_ip: \"300.1.1.1\" is not a valid IP address");
+
+        let err = config(json!({ "port_manager_port": "http" })).validate().unwrap_err();
+        assert_eq!(err, "port_manager_port: \"http\" is not a valid port number");
+
+        let url = config(json!({ "port_manager_ip": "::1", "port_manager_endpoint": "/getport" })).port_manager_url().unwrap();
+        assert_eq!(url.as_str(), "http://[::1]:1030/getport");
+        assert_eq!(config(json!({})).validate(), Ok(()));
+    }
+}
@@ -0,0 +1,139 @@
+use actix_web::{get, rt, web, Error, HttpRequest, HttpResponse};
+use actix_ws::{Message, Session};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::{clamp_to_max, run_generate, GenerateOutput, GenerateParams, RecentValues, ServiceError, CHAOS_MARKER, MAX_LENGTH};
+use crate::utils::Config;
+
+
+
+const MAX_TOTAL: usize = 1_000_000;
+const DEFAULT_BATCH_SIZE: usize = 1000;
+const MAX_BATCH_SIZE: usize = 10_000;
+
+
+
+// One text message per job: the /generate_random_string parameters plus how many values to produce
+#[derive(Deserialize)]
+struct GenerationSpec {
+    total: usize,
+    batch_size: Option<usize>,
+    time_budget_ms: Option<u64>,
+    #[serde(flatten)]
+    params: GenerateParams,
+}
+
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Frame {
+    Batch { values: Vec<GenerateOutput> },
+    Progress { done: usize, total: usize },
+    Complete {
+        total: usize,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        budget_exhausted: bool,
+    },
+    Error { message: String },
+}
+
+
+
+#[get("/ws/generate")]
+pub async fn ws_generate_handler(req: HttpRequest, body: web::Payload, config: web::Data<Config>, recent: web::Data<RecentValues>) -> Result<HttpResponse, Error> {
+    let peer = req.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "Unknown".into());
+    info!(target: "ws_generate_handler", "WebSocket opened from: {}", peer);
+
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+
+    rt::spawn(async move {
+        while let Some(Ok(message)) = messages.recv().await {
+            let outcome = match message {
+                Message::Text(text) => run_job(&text, &mut session, &config, &recent).await,
+                Message::Ping(bytes) => session.pong(&bytes).await,
+                Message::Close(reason) => {
+                    let _ = session.close(reason).await;
+                    break;
+                }
+                _ => Ok(()),
+            };
+
+            // The client went away; whatever was left of the job is dropped with it
+            if outcome.is_err() {
+                info!(target: "ws_generate_handler", "Client {} disconnected", peer);
+                break;
+            }
+        }
+    });
+
+    Ok(response)
+}
+
+
+// Err only when the socket is closed; invalid specs and generation failures are reported as error frames
+async fn run_job(text: &str, session: &mut Session, config: &Config, recent: &RecentValues) -> Result<(), actix_ws::Closed> {
+    let mut spec: GenerationSpec = match serde_json::from_str(text) {
+        Ok(spec) => spec,
+        Err(e) => return send(session, &Frame::Error { message: format!("Invalid generation spec: {}", e) }).await,
+    };
+
+    let batch_size = spec.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    if spec.total == 0 || spec.total > MAX_TOTAL || batch_size == 0 || batch_size > MAX_BATCH_SIZE {
+        let message = format!("total must be 1–{} and batch_size 1–{}", MAX_TOTAL, MAX_BATCH_SIZE);
+        return send(session, &Frame::Error { message }).await;
+    }
+
+    // Clamped once here, so a long job warns once rather than for every value
+    spec.params.length = clamp_to_max("length", spec.params.length, MAX_LENGTH, config);
+
+    // Seeded jobs advance the counter per value, so the whole job is reproducible without repeating itself
+    let first_counter = spec.params.counter.unwrap_or(0);
+    if spec.params.seed.is_some() && first_counter.checked_add(spec.total as u64 - 1).is_none() {
+        let message = format!("counter {} leaves no room for {} values before the counter overflows", first_counter, spec.total);
+        return send(session, &Frame::Error { message }).await;
+    }
+    let deadline = spec.time_budget_ms.map(|budget| Instant::now() + Duration::from_millis(budget));
+    let out_of_time = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let mut done = 0;
+
+    while done < spec.total && !out_of_time() {
+        let mut values = Vec::with_capacity(batch_size.min(spec.total - done));
+        while values.len() < batch_size && done + values.len() < spec.total && !out_of_time() {
+            if spec.params.seed.is_some() {
+                spec.params.counter = Some(first_counter + (done + values.len()) as u64);
+            }
+
+            match run_generate(&spec.params, config, recent) {
+                Ok(generated) => values.push(generated.output),
+                Err(err) => {
+                    let message = match err {
+                        ServiceError::Invalid(msg) | ServiceError::TooLarge(msg) => msg,
+                        ServiceError::Internal(_) => "Internal server error".to_string(),
+                        ServiceError::Injected => CHAOS_MARKER.to_string(),
+                    };
+                    warn!(target: "ws_generate_handler", "Job stopped after {} values: {}", done + values.len(), message);
+                    return send(session, &Frame::Error { message }).await;
+                }
+            }
+        }
+
+        done += values.len();
+        send(session, &Frame::Batch { values }).await?;
+        send(session, &Frame::Progress { done, total: spec.total }).await?;
+
+        // Lets the worker serve other requests between batches of a long job
+        tokio::task::yield_now().await;
+    }
+
+    // A job cut short by its time budget still ends normally, just with fewer values
+    let budget_exhausted = done < spec.total;
+    info!(target: "ws_generate_handler", "Job completed with {} of {} values", done, spec.total);
+    send(session, &Frame::Complete { total: done, budget_exhausted }).await
+}
+
+
+async fn send(session: &mut Session, frame: &Frame) -> Result<(), actix_ws::Closed> {
+    session.text(serde_json::to_string(frame).unwrap_or_default()).await
+}